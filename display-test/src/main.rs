@@ -1,5 +1,6 @@
 use midi_parse::datatypes::DrumTrack;
-use midi_parse::map::{process_track_pool, NUMBER_OF_TRACKS, RESOLUTION};
+use midi_parse::diagnostics::Diagnostics;
+use midi_parse::map::{get_layout, process_track_pool, DEFAULT_LAYOUT, NUMBER_OF_TRACKS, RESOLUTION};
 use midi_parse::parse::filter_beat;
 use midly::Smf;
 use ndarray::{Array, ArrayView, Ix3};
@@ -40,9 +41,11 @@ fn get_midi_data() -> Vec<Array<f32, Ix3>> {
     let data = fs::read(&opt.input).expect(&file_input_error_message);
     // parse midi data
     let track_pool: Vec<DrumTrack> =
-        filter_beat(Smf::parse(&data).expect("could not parse SMF data"), true);
+        filter_beat(Smf::parse(&data).expect("could not parse SMF data"), true)
+            .expect("could not filter beat tracks");
     // get ndarray version
-    process_track_pool(&track_pool)
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    process_track_pool(&track_pool, &layout, &mut Diagnostics::new())
         .expect("Failed to cast tracks into ndarray 4")
         .outer_iter()
         .map(|bar_view: ArrayView<f32, Ix3>| bar_view.to_owned())
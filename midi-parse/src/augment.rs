@@ -0,0 +1,326 @@
+use ndarray::{s, Array, Axis, Ix4};
+
+use crate::datatypes::Bar;
+use crate::error::MidiBeatError;
+use crate::map::Layout;
+
+// lowest velocity of a hit, scaled and jittered hits never vanish
+const MIN_VELOCITY: f32 = 1. / 127.;
+const MAX_OFFSET: f32 = 0.5;
+// swung pairs per bar, 16th swing for 4/4 bars, 4 for 8th swing
+const SWING_PAIRS_PER_BAR: usize = 8;
+
+// splitmix64, seedable and stable across platforms
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn new(seed: u64) -> Rng {
+        Rng { state: seed }
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // uniform in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    // uniform in [low, high)
+    pub fn range(&mut self, low: f32, high: f32) -> f32 {
+        low + (high - low) * self.next_f32()
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Augmentation {
+    // velocities multiplied by a factor drawn once per row in [min, max)
+    VelocityScale { min: f32, max: f32 },
+    // velocity of each hit moved by up to amount
+    VelocityJitter { amount: f32 },
+    // offset of each hit moved by up to amount of a step, within the offset range
+    TimingJitter { amount: f32 },
+    // each lane of a row silenced with probability, a row is never fully silenced
+    LaneDropout { probability: f32 },
+    // hits moved by steps within their bar, wrapping around
+    Rotate { steps: isize },
+    // off-beat of each of the pairs of a bar delayed, amount 1 is a triplet feel
+    Swing { amount: f32, pairs_per_bar: usize },
+    LaneSwap { a: usize, b: usize },
+    // first half of each bar stretched to the whole bar
+    HalfTime,
+    // each bar compressed to a half bar, played twice
+    DoubleTime,
+}
+
+// puts a hit at a position in steps, the louder hit wins on collisions,
+// out of bar positions wrap around or are dropped
+fn place(bar: &mut Bar, position: f32, lane: usize, velocity: f32, wrap: bool) {
+    let number_of_steps = bar.number_of_steps() as isize;
+    let step = position.round();
+    let offset = (position - step).clamp(-MAX_OFFSET, MAX_OFFSET);
+
+    let step = if wrap {
+        (step as isize).rem_euclid(number_of_steps)
+    } else if step < 0. || step as isize >= number_of_steps {
+        return;
+    } else {
+        step as isize
+    } as usize;
+
+    if velocity > bar.velocity(step, lane) {
+        bar.set(step, lane, velocity, offset);
+    }
+}
+
+// (step, lane, velocity, offset) of each hit
+fn hits(bar: &Bar) -> Vec<(usize, usize, f32, f32)> {
+    (0..bar.number_of_steps())
+        .flat_map(|step| (0..bar.number_of_lanes()).map(move |lane| (step, lane)))
+        .filter(|&(step, lane)| bar.hit(step, lane))
+        .map(|(step, lane)| (step, lane, bar.velocity(step, lane), bar.offset(step, lane)))
+        .collect()
+}
+
+// same hits at new positions
+fn move_hits(bar: &Bar, wrap: bool, positions: impl Fn(usize, f32) -> Vec<f32>) -> Bar {
+    let mut moved = Bar::new(bar.number_of_steps(), bar.number_of_lanes());
+    for (step, lane, velocity, offset) in hits(bar) {
+        for position in positions(step, offset) {
+            place(&mut moved, position, lane, velocity, wrap);
+        }
+    }
+    moved
+}
+
+// same hits with new velocities and offsets
+fn move_hits_with(bar: &Bar, mut change: impl FnMut(f32, f32) -> (f32, f32)) -> Bar {
+    let mut changed = bar.clone();
+    for (step, lane, velocity, offset) in hits(bar) {
+        let (velocity, offset) = change(velocity, offset);
+        changed.set(step, lane, velocity, offset);
+    }
+    changed
+}
+
+fn clamp_velocity(velocity: f32) -> f32 {
+    velocity.clamp(MIN_VELOCITY, 1.)
+}
+
+impl Augmentation {
+    // "name=parameters", lanes of swaps are layout lane names or indexes
+    pub fn parse(spec: &str, layout: &Layout) -> Result<Augmentation, MidiBeatError> {
+        let error = |message: &str| MidiBeatError::Config(format!("augmentation '{}': {}", spec, message));
+        let number = |value: &str| value.trim().parse::<f32>().map_err(|_| error("expected a number"));
+        let lane = |value: &str| {
+            layout
+                .lane_index(value)
+                .ok_or_else(|| error(&format!("no lane {} in layout {}", value.trim(), layout.name)))
+        };
+
+        let mut parts = spec.splitn(2, '=');
+        let name = parts.next().unwrap_or("").trim();
+        let value = parts.next().unwrap_or("");
+        let pair = || {
+            let mut values = value.splitn(2, ':');
+            (values.next().unwrap_or(""), values.next())
+        };
+
+        let augmentation = match name {
+            "velocity_scale" => {
+                let (min, max) = pair();
+                let min = number(min)?;
+                Augmentation::VelocityScale {
+                    min,
+                    max: max.map(number).transpose()?.unwrap_or(min),
+                }
+            }
+            "velocity_jitter" => Augmentation::VelocityJitter { amount: number(value)? },
+            "timing_jitter" => Augmentation::TimingJitter { amount: number(value)? },
+            "lane_dropout" => Augmentation::LaneDropout {
+                probability: number(value)?,
+            },
+            "rotate" => Augmentation::Rotate {
+                steps: value.trim().parse().map_err(|_| error("expected a number of steps"))?,
+            },
+            "swing" => {
+                let (amount, pairs_per_bar) = pair();
+                Augmentation::Swing {
+                    amount: number(amount)?,
+                    pairs_per_bar: match pairs_per_bar {
+                        Some(pairs_per_bar) => pairs_per_bar
+                            .trim()
+                            .parse()
+                            .map_err(|_| error("expected a number of pairs per bar"))?,
+                        None => SWING_PAIRS_PER_BAR,
+                    },
+                }
+            }
+            "swap" => match pair() {
+                (a, Some(b)) => Augmentation::LaneSwap { a: lane(a)?, b: lane(b)? },
+                _ => return Err(error("expected two lanes, like closed_hh:ride")),
+            },
+            "half_time" => Augmentation::HalfTime,
+            "double_time" => Augmentation::DoubleTime,
+            _ => return Err(error("unknown augmentation")),
+        };
+
+        Ok(augmentation)
+    }
+
+    // bars of one row (window, or context and target), row level parameters are drawn once
+    pub fn apply(&self, bars: &[Bar], rng: &mut Rng) -> Vec<Bar> {
+        match self {
+            Augmentation::VelocityScale { min, max } => {
+                let factor = rng.range(*min, *max);
+                bars.iter()
+                    .map(|bar| move_hits_with(bar, |velocity, offset| (clamp_velocity(velocity * factor), offset)))
+                    .collect()
+            }
+            Augmentation::VelocityJitter { amount } => bars
+                .iter()
+                .map(|bar| {
+                    move_hits_with(bar, |velocity, offset| {
+                        (clamp_velocity(velocity + rng.range(-amount, *amount)), offset)
+                    })
+                })
+                .collect(),
+            Augmentation::TimingJitter { amount } => bars
+                .iter()
+                .map(|bar| {
+                    move_hits_with(bar, |velocity, offset| {
+                        let offset = (offset + rng.range(-amount, *amount)).clamp(-MAX_OFFSET, MAX_OFFSET);
+                        (velocity, offset)
+                    })
+                })
+                .collect(),
+            Augmentation::LaneDropout { probability } => {
+                let number_of_lanes = bars.first().map(|bar| bar.number_of_lanes()).unwrap_or(0);
+                let dropped: Vec<bool> = (0..number_of_lanes).map(|_| rng.next_f32() < *probability).collect();
+
+                let mut augmented: Vec<Bar> = bars.to_vec();
+                augmented.iter_mut().for_each(|bar| {
+                    for (step, lane, _, _) in hits(bar) {
+                        if dropped[lane] {
+                            bar.set(step, lane, 0., 0.);
+                        }
+                    }
+                });
+
+                if augmented.iter().all(|bar| bar.is_silent()) {
+                    bars.to_vec()
+                } else {
+                    augmented
+                }
+            }
+            Augmentation::Rotate { steps } => bars
+                .iter()
+                .map(|bar| move_hits(bar, true, |step, offset| vec![(step as isize + steps) as f32 + offset]))
+                .collect(),
+            Augmentation::Swing { amount, pairs_per_bar } => bars
+                .iter()
+                .map(|bar| {
+                    let period = bar.number_of_steps() / (*pairs_per_bar).max(1);
+                    move_hits(bar, false, |step, offset| {
+                        let position = step as f32 + offset;
+                        if period >= 2 && step % period == period / 2 {
+                            // a triplet off-beat is a sixth of the pair later than a straight one
+                            vec![position + amount * period as f32 / 6.]
+                        } else {
+                            vec![position]
+                        }
+                    })
+                })
+                .collect(),
+            Augmentation::LaneSwap { a, b } => bars
+                .iter()
+                .map(|bar| {
+                    let mut swapped = bar.clone();
+                    for step in 0..bar.number_of_steps() {
+                        swapped.set(step, *a, bar.velocity(step, *b), bar.offset(step, *b));
+                        swapped.set(step, *b, bar.velocity(step, *a), bar.offset(step, *a));
+                    }
+                    swapped
+                })
+                .collect(),
+            Augmentation::HalfTime => bars
+                .iter()
+                .map(|bar| move_hits(bar, false, |step, offset| vec![2. * (step as f32 + offset)]))
+                .collect(),
+            Augmentation::DoubleTime => bars
+                .iter()
+                .map(|bar| {
+                    let half = bar.number_of_steps() as f32 / 2.;
+                    move_hits(bar, false, |step, offset| {
+                        let position = (step as f32 + offset) / 2.;
+                        vec![position, position + half]
+                    })
+                })
+                .collect(),
+        }
+    }
+}
+
+// comma separated augmentations, applied in order
+pub fn parse_augmentations(spec: &str, layout: &Layout) -> Result<Vec<Augmentation>, MidiBeatError> {
+    spec.split(',')
+        .filter(|part| !part.trim().is_empty())
+        .map(|part| Augmentation::parse(part, layout))
+        .collect()
+}
+
+pub fn augment_bars(bars: &[Bar], augmentations: &[Augmentation], rng: &mut Rng) -> Vec<Bar> {
+    augmentations
+        .iter()
+        .fold(bars.to_vec(), |bars, augmentation| augmentation.apply(&bars, rng))
+}
+
+// augments in place the rows of a dataset array, rows are cut in bars of steps_per_bar steps,
+// the target bar of pairs is augmented with its context, tags are 0 for originals and
+// 1 + index in chains for augmented copies
+pub fn augment_rows(
+    array: &mut Array<f32, Ix4>,
+    mut targets: Option<&mut Array<f32, Ix4>>,
+    tags: &[usize],
+    chains: &[Vec<Augmentation>],
+    steps_per_bar: usize,
+    rng: &mut Rng,
+) -> Result<(), MidiBeatError> {
+    for (row, &tag) in tags.iter().enumerate() {
+        if tag == 0 {
+            continue;
+        }
+
+        let mut bars: Vec<Bar> = array
+            .index_axis(Axis(0), row)
+            .axis_chunks_iter(Axis(0), steps_per_bar)
+            .map(Bar::from_view)
+            .collect::<Result<Vec<Bar>, MidiBeatError>>()?;
+        let context_bars = bars.len();
+        if let Some(targets) = &targets {
+            bars.push(Bar::from_view(targets.index_axis(Axis(0), row))?);
+        }
+
+        let augmented = augment_bars(&bars, &chains[tag - 1], rng);
+
+        for (bar_index, bar) in augmented.iter().take(context_bars).enumerate() {
+            let start = bar_index * steps_per_bar;
+            array
+                .slice_mut(s![row, start..start + bar.number_of_steps(), .., ..])
+                .assign(&bar.view());
+        }
+        if let (Some(targets), Some(target)) = (targets.as_mut(), augmented.get(context_bars)) {
+            targets.index_axis_mut(Axis(0), row).assign(&target.view());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,157 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::datatypes::DrumTrack;
+use crate::diagnostics::Diagnostics;
+use crate::error::MidiBeatError;
+use crate::map::{get_track_grids, Layout, TrackGrid};
+use crate::parse::parse_bytes;
+use crate::serialization::{from_binary, to_binary};
+use crate::utils::content_hash;
+
+// bumped when parsing or gridding changes, so that older entries are ignored
+pub const CACHE_VERSION: u32 = 2;
+
+// grids of the tracks of a file, or why a track can't be gridded, track indices are in the file
+type GridEntry = Vec<Result<TrackGrid, (usize, String)>>;
+
+// parsed tracks and grids of each file keyed by content hash, rebuilding a corpus only parses and
+// grids new or changed files. Files which can't be parsed and tracks which can't be gridded are
+// cached too, with their error. Entries are written as soon as a file is parsed or gridded, so an
+// interrupted build resumes where it stopped
+pub struct ParseCache {
+    directory: PathBuf,
+    pub hits: u64,
+    pub misses: u64,
+    pub grid_hits: u64,
+    pub grid_misses: u64,
+}
+
+// grids only depend on the lanes and their keys, the alt map is sorted so that the key is stable
+fn get_layout_key(layout: &Layout) -> String {
+    let alt_map: BTreeMap<&u8, &Vec<usize>> = layout.alt_map.iter().collect();
+    content_hash(format!("{:?} {:?} {}", layout.perc_map, alt_map, layout.merge_keys).as_bytes())
+}
+
+impl ParseCache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Result<ParseCache, MidiBeatError> {
+        let directory = directory.into();
+        fs::create_dir_all(&directory)?;
+
+        Ok(ParseCache {
+            directory,
+            hits: 0,
+            misses: 0,
+            grid_hits: 0,
+            grid_misses: 0,
+        })
+    }
+
+    fn entry_path(&self, hash: &str, drum_channel: bool) -> PathBuf {
+        let channel = if drum_channel { "drums" } else { "all" };
+        self.directory.join(format!("{}_{}_v{}.bin", hash, channel, CACHE_VERSION))
+    }
+
+    fn grid_entry_path(&self, hash: &str, drum_channel: bool, layout: &Layout, resolution: usize) -> PathBuf {
+        let channel = if drum_channel { "drums" } else { "all" };
+        self.directory.join(format!(
+            "{}_{}_{}_{}_grid_v{}.bin",
+            hash,
+            channel,
+            get_layout_key(layout),
+            resolution,
+            CACHE_VERSION
+        ))
+    }
+
+    // unreadable entries are misses, they get overwritten
+    fn read<T: DeserializeOwned>(path: PathBuf) -> Option<T> {
+        let bytes = fs::read(path).ok()?;
+        from_binary(&bytes).ok()
+    }
+
+    // written to a temporary file then renamed, an interrupted write never leaves a partial entry
+    fn write<T: Serialize>(path: PathBuf, value: &T) -> Result<(), MidiBeatError> {
+        let temporary_path = path.with_extension("tmp");
+        fs::write(&temporary_path, to_binary(value)?)?;
+        fs::rename(&temporary_path, &path)?;
+        Ok(())
+    }
+
+    // tracks of a file, or the message of its parse error
+    pub fn get(&self, hash: &str, drum_channel: bool) -> Option<Result<Vec<DrumTrack>, String>> {
+        ParseCache::read(self.entry_path(hash, drum_channel))
+    }
+
+    pub fn put(
+        &self,
+        hash: &str,
+        drum_channel: bool,
+        tracks: &Result<Vec<DrumTrack>, String>,
+    ) -> Result<(), MidiBeatError> {
+        ParseCache::write(self.entry_path(hash, drum_channel), tracks)
+    }
+
+    // tracks of a file content, parsed only on cache misses.
+    // A failed cache write is reported, the parsed tracks are still returned
+    pub fn parse(
+        &mut self,
+        data: &[u8],
+        drum_channel: bool,
+        diagnostics: &mut Diagnostics,
+    ) -> Result<Vec<DrumTrack>, MidiBeatError> {
+        let hash = content_hash(data);
+        if let Some(tracks) = self.get(&hash, drum_channel) {
+            self.hits += 1;
+            return tracks.map_err(MidiBeatError::Cached);
+        }
+
+        self.misses += 1;
+        let tracks = parse_bytes(data, drum_channel);
+        let entry = tracks.as_ref().cloned().map_err(|e| e.to_string());
+        if let Err(e) = self.put(&hash, drum_channel, &entry) {
+            diagnostics.warn("cache_write_failed", None, e.to_string());
+        }
+        tracks
+    }
+
+    // get_track_grids of the tracks of a file with the given content hash, gridded only on cache
+    // misses, track indices are indices in the file
+    pub fn grids(
+        &mut self,
+        hash: &str,
+        drum_channel: bool,
+        tracks: &[DrumTrack],
+        layout: &Layout,
+        resolution: usize,
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<Result<TrackGrid, (usize, MidiBeatError)>> {
+        let path = self.grid_entry_path(hash, drum_channel, layout, resolution);
+        let entry: GridEntry = match ParseCache::read(path.clone()) {
+            Some(entry) => {
+                self.grid_hits += 1;
+                entry
+            }
+            None => {
+                self.grid_misses += 1;
+                let entry: GridEntry = get_track_grids(tracks, layout, resolution)
+                    .into_iter()
+                    .map(|track_grid| track_grid.map_err(|(track_index, e)| (track_index, e.to_string())))
+                    .collect();
+                if let Err(e) = ParseCache::write(path, &entry) {
+                    diagnostics.warn("cache_write_failed", None, e.to_string());
+                }
+                entry
+            }
+        };
+
+        entry
+            .into_iter()
+            .map(|track_grid| track_grid.map_err(|(track_index, message)| (track_index, MidiBeatError::Cached(message))))
+            .collect()
+    }
+}
@@ -1,28 +1,67 @@
 use itertools::Itertools;
+use ndarray::{s, Array, ArrayView, Axis, ErrorKind, Ix2, Ix3, Ix4, ShapeError};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
 use time_calc::TimeSig;
 
-use crate::map::{get_perc_map, NUMBER_OF_TRACKS, RESOLUTION, get_alt_reverse_perc_map};
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+use crate::error::MidiBeatError;
+use crate::map::{get_perc_map, RESOLUTION, get_alt_reverse_perc_map};
 use crate::utils::{div_rem_usize, normalize_offset, normalize_velocity};
 
-#[derive(Copy, Clone, Eq, PartialEq)]
+// how a key got its lane in a track perc map
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+#[cfg_attr(feature = "serialization", serde(rename_all = "lowercase"))]
+pub enum MappingSource {
+    // most played key of the lane group
+    Primary,
+    // key moved to a free lane through the alt map
+    Fallback,
+}
+
+// MIDI default tempo, 120 BPM
+pub const DEFAULT_TEMPO: u32 = 500_000;
+// time signature of tracks without a time signature event, meant as 4/4 although the
+// denominator of time signature events is a power of 2
+pub const DEFAULT_TIME_SIGNATURE: (u8, u8, u8, u8) = (4, 4, 0, 0);
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
 pub struct Drum {
     pub time: u32,
     pub velocity: u8,
     pub key: u8,
 }
+
+#[derive(Debug, PartialEq)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
 pub struct DrumTrack {
     pub events: Vec<Drum>,
     pub time_signature: (u8, u8, u8, u8),
     pub ppqn: u16,
+    // microseconds per quarter note, first tempo of the file
+    pub tempo: u32,
+    // marker meta events of the file, (tick, text)
+    pub markers: Vec<(u32, String)>,
+    // labels joined from a dataset info file, column -> value
+    pub metadata: BTreeMap<String, String>,
 }
 
 impl Clone for DrumTrack {
     fn clone(&self) -> DrumTrack {
-        let ev = self.events.iter().cloned().collect();
+        let ev = self.events.to_vec();
         DrumTrack {
             events: ev,
             time_signature: self.time_signature,
             ppqn: self.ppqn,
+            tempo: self.tempo,
+            markers: self.markers.clone(),
+            metadata: self.metadata.clone(),
         }
     }
 }
@@ -42,52 +81,72 @@ impl DrumTrack {
             events,
             time_signature: ts,
             ppqn,
+            tempo: DEFAULT_TEMPO,
+            markers: vec![],
+            metadata: BTreeMap::new(),
         }
     }
 
     pub fn get_key_footprint(&self) -> Vec<u8> {
-        let k = self
-            .events
+        self.events
             .iter()
             .map(|e| e.key)
             .unique()
             .sorted()
-            .collect();
-
-        let perc_map = get_perc_map();
+            .collect()
+    }
 
+    // keys of the track which are not listed in perc_map, with their number of events
+    pub fn get_unmapped_keys(&self, perc_map: &[Vec<u8>]) -> Vec<(u8, usize)> {
         // Flatten perc_map to a single Vec
         let flat_perc_map: Vec<u8> = perc_map.iter().flat_map(|v| v.iter()).cloned().collect();
 
-        // Check for keys in k not present in flat_perc_map and print a warning
-        for key in &k {
-            if !flat_perc_map.contains(key) && (*key > 35 && *key < 82) {
-                println!("Warning: Key {} is not listed in perc_map", key);
-            }
-        }
-        k
+        self.get_key_footprint()
+            .into_iter()
+            .filter(|key| !flat_perc_map.contains(key))
+            .map(|key| (key, self.events.iter().filter(|e| e.key == key).count()))
+            .collect()
     }
 
-    // this is highly opinionated
     pub fn get_track_perc_map(&self) -> Vec<Option<u8>> {
-        let perc_map = get_perc_map();
+        self.get_track_perc_map_with(&get_perc_map(), &get_alt_reverse_perc_map())
+    }
+
+    pub fn get_track_perc_map_with(
+        &self,
+        perc_map: &[Vec<u8>],
+        alt_map: &HashMap<u8, Vec<usize>>,
+    ) -> Vec<Option<u8>> {
+        self.get_track_perc_map_decisions(perc_map, alt_map)
+            .into_iter()
+            .map(|decision| decision.map(|(key, _)| key))
+            .collect()
+    }
 
+    // this is highly opinionated
+    // same as get_track_perc_map_with, also tells whether the key of each lane is a fallback
+    pub fn get_track_perc_map_decisions(
+        &self,
+        perc_map: &[Vec<u8>],
+        alt_map: &HashMap<u8, Vec<usize>>,
+    ) -> Vec<Option<(u8, MappingSource)>> {
         let key_footprint = self.get_key_footprint();
 
-        let mut mapped : Vec<Option<u8>> = perc_map
+        let mut mapped : Vec<Option<(u8, MappingSource)>> = perc_map
             .iter()
             .enumerate()
-            .map(|(idx, perc_group)| self.get_key_for_group(idx, perc_group, &key_footprint))
+            .map(|(idx, perc_group)| {
+                self.get_key_for_group(idx, perc_group, &key_footprint)
+                    .map(|key| (key, MappingSource::Primary))
+            })
             .collect();
 
         let not_in_mapped: Vec<u8> = key_footprint
             .into_iter()
-            .filter(|&k| !mapped.iter().any(|&m| m == Some(k)))
+            .filter(|&k| !mapped.iter().any(|m| m.map(|(key, _)| key) == Some(k)))
             .collect();
 
         // println!("not_in_mapped: {:?}", not_in_mapped);
-        
-        let alt_map = get_alt_reverse_perc_map();
 
         // for each not_in_mapped key, find an alternative group in alt_map.
         // alt_map is an hash map, can be queried by key, it gives a vector of groups in order of preference.
@@ -104,9 +163,8 @@ impl DrumTrack {
                     // Check if this group is free (index in mapped is None).
                     if let Some(slot) = mapped.get(*group) {
                         if slot.is_none() {
-                            println!("Found alternative group for key {} in group {}", key, group);
                             // If the slot is free, assign it the key
-                            mapped[*group] = Some(key);
+                            mapped[*group] = Some((key, MappingSource::Fallback));
                             break;
                         }
                     }
@@ -129,14 +187,26 @@ impl DrumTrack {
     }
 
     pub fn get_step_track_duration(&self) -> usize {
+        self.get_step_track_duration_with(RESOLUTION)
+    }
+
+    pub fn get_step_track_duration_with(&self, resolution: usize) -> usize {
         let bar_tick_duration = self.get_bar_track_duration();
-        bar_tick_duration / RESOLUTION
+        bar_tick_duration / resolution
     }
 
     pub fn to_grid(
         &self,
         perc_map: &Vec<Option<u8>>,
-    ) -> Vec<[[[f32; 2]; NUMBER_OF_TRACKS]; RESOLUTION]> {
+    ) -> Result<Vec<Bar>, MidiBeatError> {
+        self.to_grid_with_resolution(perc_map, RESOLUTION)
+    }
+
+    pub fn to_grid_with_resolution(
+        &self,
+        perc_map: &Vec<Option<u8>>,
+        resolution: usize,
+    ) -> Result<Vec<Bar>, MidiBeatError> {
         let unwrapped_perc_map: Vec<u8> = perc_map
             .iter()
             .map(|option_key| match option_key {
@@ -145,28 +215,34 @@ impl DrumTrack {
             })
             .collect();
 
-        // duration of a step (bar / RESOLUTION) in ticks
-        let step_tick_duration = self.get_step_track_duration();
+        // duration of a step (bar / resolution) in ticks
+        let step_tick_duration = self.get_step_track_duration_with(resolution);
+        if step_tick_duration == 0 {
+            return Err(MidiBeatError::InvalidStepDuration {
+                ppqn: self.ppqn,
+                time_signature: self.time_signature,
+                resolution,
+            });
+        }
         // minimum of distance between 2 events on a same step
         // let's see if we need it or not
         // let minimum_distance: f32 = 0.05;
 
         // last event of the track, since track is already sorted, this gives us the length of our grid vector
-        let last_event: &Drum = self.events.last().unwrap();
+        let last_event: &Drum = self.events.last().ok_or(MidiBeatError::EmptyTrack)?;
         let (event_len, _): (usize, usize) =
             self.get_step_index_offset_tuple(last_event, step_tick_duration);
         let safe_len = event_len + 1;
 
-        // calculate grid len in multiples of RESOLUTION
-        let mut bars_number = safe_len / RESOLUTION;
-        if safe_len % RESOLUTION > 0 {
+        // calculate grid len in multiples of resolution
+        let mut bars_number = safe_len / resolution;
+        if safe_len % resolution > 0 {
             bars_number += 1
         }
-        let grid_len = bars_number * RESOLUTION;
+        let grid_len = bars_number * resolution;
 
         // data structure to be filled from track events
-        let mut grid: Vec<[[f32; 2]; NUMBER_OF_TRACKS]> =
-            vec![[[0., 0.]; NUMBER_OF_TRACKS]; grid_len];
+        let mut grid: Vec<Vec<[f32; NUMBER_OF_FEATURES]>> = vec![vec![[0., 0.]; perc_map.len()]; grid_len];
 
         // parsing and filling the grid
         self.events
@@ -188,12 +264,12 @@ impl DrumTrack {
                     // so here we can check for next event
 
                     // here we need to check if there's an event on next step already
-                    if event_payload[1] > 0.5 {
+                    if event_payload[OFFSET] > 0.5 {
                         match grid[grid_index + 1][perc_index] {
                             // there is no event on next step, we put event on next step with negative offset
                             [next_vel, next_offset] if next_vel == 0. && next_offset == 0. => {
                                 grid[grid_index + 1][perc_index] =
-                                    [event_payload[0], event_payload[1] - 1.]
+                                    [event_payload[VELOCITY], event_payload[OFFSET] - 1.]
                             }
                             [next_vel, _next_offset] => {
                                 // there is an event on next step,
@@ -204,10 +280,10 @@ impl DrumTrack {
                                     }
                                     [vel, _offset] => {
                                         // else we check if next event has a lower velocity
-                                        if event_payload[0] > next_vel {
+                                        if event_payload[VELOCITY] > next_vel {
                                             grid[grid_index + 1][perc_index] =
-                                                [event_payload[0], event_payload[1] - 1.]
-                                        } else if event_payload[0] > vel {
+                                                [event_payload[VELOCITY], event_payload[OFFSET] - 1.]
+                                        } else if event_payload[VELOCITY] > vel {
                                             // or if current event has a lower velocity
                                             grid[grid_index][perc_index] = event_payload
                                         }
@@ -229,18 +305,18 @@ impl DrumTrack {
                                         if next_vel == 0. && next_offset == 0. =>
                                     {
                                         grid[grid_index + 1][perc_index] =
-                                            [event_payload[0], event_payload[1] - 1.]
+                                            [event_payload[VELOCITY], event_payload[OFFSET] - 1.]
                                     }
                                     // there is something so if velociy is higher we replace it
                                     [next_vel, _next_offset] => {
-                                        if event_payload[0] > vel {
+                                        if event_payload[VELOCITY] > vel {
                                             // and at last, if velocity is higher than current step event, we replace it
                                             grid[grid_index][perc_index] =
-                                                [event_payload[0], event_payload[1]]
-                                        } else if event_payload[0] > next_vel {
+                                                [event_payload[VELOCITY], event_payload[OFFSET]]
+                                        } else if event_payload[VELOCITY] > next_vel {
                                             // last case scenario we check if next event velocity is lower
                                             grid[grid_index + 1][perc_index] =
-                                                [event_payload[0], event_payload[1] - 1.]
+                                                [event_payload[VELOCITY], event_payload[OFFSET] - 1.]
                                         }
                                     }
                                 }
@@ -251,15 +327,10 @@ impl DrumTrack {
             });
 
         grid[..]
-            .chunks_exact(RESOLUTION as usize)
-            .map(|chunk: &[[[f32; 2]; NUMBER_OF_TRACKS]]| {
-                let mut bar = [[[0. as f32; 2]; NUMBER_OF_TRACKS]; RESOLUTION];
-                chunk.iter().enumerate().for_each(|(step_index, step)| {
-                    step.iter().enumerate().for_each(|(perc_index, event)| {
-                        bar[step_index][perc_index] = *event;
-                    })
-                });
-                bar
+            .chunks_exact(resolution)
+            .map(|chunk: &[Vec<[f32; NUMBER_OF_FEATURES]>]| {
+                let bar: Vec<f32> = chunk.iter().flatten().flatten().cloned().collect();
+                Bar::from_array(Array::from_shape_vec((resolution, perc_map.len(), NUMBER_OF_FEATURES), bar)?)
             })
             .collect()
     }
@@ -269,13 +340,16 @@ impl DrumTrack {
 
         if has_any_key_for_group {
             let key_events_sorted_by_occurences: Vec<(u8, usize)> = perc_group
-                .into_iter()
+                .iter()
                 .map(|&key| (key, self.events.iter().filter(|e| e.key == key).count()))
                 .filter(|&(_, count)| count > 0)
                 .sorted_by(|a, b| b.1.cmp(&a.1))
                 .collect();
 
-            let max_nb_of_events = key_events_sorted_by_occurences[0].1;
+            let max_nb_of_events = match key_events_sorted_by_occurences.first() {
+                Some((_, count)) => *count,
+                None => return None,
+            };
 
             let key_events: Vec<u8> = key_events_sorted_by_occurences
                 .iter()
@@ -283,12 +357,10 @@ impl DrumTrack {
                 .map(|(key, _)| *key)
                 .collect();
 
-            let &chosen_key = perc_group
+            return perc_group
                 .iter()
                 .find(|&&key| key_events.contains(&key))
-                .unwrap();
-
-            return Some(chosen_key);
+                .cloned();
         }
 
         None
@@ -304,3 +376,190 @@ impl DrumTrack {
         (quotient, rest)
     }
 }
+
+// feature indexes of a grid event
+pub const VELOCITY: usize = 0;
+pub const OFFSET: usize = 1;
+pub const NUMBER_OF_FEATURES: usize = 2;
+
+// one bar of the grid, (steps, lanes, [velocity, offset])
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Bar {
+    data: Array<f32, Ix3>,
+}
+
+impl Bar {
+    pub fn new(number_of_steps: usize, number_of_lanes: usize) -> Bar {
+        Bar {
+            data: Array::zeros((number_of_steps, number_of_lanes, NUMBER_OF_FEATURES)),
+        }
+    }
+
+    pub fn from_array(data: Array<f32, Ix3>) -> Result<Bar, MidiBeatError> {
+        if data.shape()[2] != NUMBER_OF_FEATURES {
+            return Err(MidiBeatError::Shape(ShapeError::from_kind(ErrorKind::IncompatibleShape)));
+        }
+
+        Ok(Bar { data })
+    }
+
+    pub fn from_view(view: ArrayView<f32, Ix3>) -> Result<Bar, MidiBeatError> {
+        Bar::from_array(view.to_owned())
+    }
+
+    pub fn view(&self) -> ArrayView<'_, f32, Ix3> {
+        self.data.view()
+    }
+
+    pub fn into_array(self) -> Array<f32, Ix3> {
+        self.data
+    }
+
+    pub fn number_of_steps(&self) -> usize {
+        self.data.shape()[0]
+    }
+
+    pub fn number_of_lanes(&self) -> usize {
+        self.data.shape()[1]
+    }
+
+    pub fn velocity(&self, step: usize, lane: usize) -> f32 {
+        self.data[[step, lane, VELOCITY]]
+    }
+
+    pub fn offset(&self, step: usize, lane: usize) -> f32 {
+        self.data[[step, lane, OFFSET]]
+    }
+
+    pub fn hit(&self, step: usize, lane: usize) -> bool {
+        self.velocity(step, lane) > 0.
+    }
+
+    pub fn set(&mut self, step: usize, lane: usize, velocity: f32, offset: f32) {
+        self.data[[step, lane, VELOCITY]] = velocity;
+        self.data[[step, lane, OFFSET]] = offset;
+    }
+
+    // (steps, lanes)
+    pub fn velocities(&self) -> ArrayView<'_, f32, Ix2> {
+        self.data.slice(s![.., .., VELOCITY])
+    }
+
+    // (steps, lanes)
+    pub fn offsets(&self) -> ArrayView<'_, f32, Ix2> {
+        self.data.slice(s![.., .., OFFSET])
+    }
+
+    // (steps, [velocity, offset]) of each lane
+    pub fn lanes(&self) -> impl Iterator<Item = ArrayView<'_, f32, Ix2>> + '_ {
+        self.data.axis_iter(Axis(1))
+    }
+
+    // (lanes, [velocity, offset]) of each step
+    pub fn steps(&self) -> impl Iterator<Item = ArrayView<'_, f32, Ix2>> + '_ {
+        self.data.axis_iter(Axis(0))
+    }
+
+    pub fn number_of_hits(&self) -> usize {
+        self.velocities().iter().filter(|&&velocity| velocity > 0.).count()
+    }
+
+    // mean velocity over the grid
+    pub fn density(&self) -> f32 {
+        self.velocities().mean().unwrap_or(0.)
+    }
+
+    pub fn is_silent(&self) -> bool {
+        self.number_of_hits() == 0
+    }
+
+    // jaccard index of the hits of two bars of the same shape, 1 for two silent bars
+    pub fn hit_similarity(&self, other: &Bar) -> f32 {
+        let pairs = self.velocities().into_iter().zip(other.velocities());
+        let (union, intersection) = pairs.fold((0, 0), |(union, intersection), (a, b)| {
+            let (a, b) = (*a > 0., *b > 0.);
+            (union + (a || b) as usize, intersection + (a && b) as usize)
+        });
+
+        if union == 0 {
+            1.
+        } else {
+            intersection as f32 / union as f32
+        }
+    }
+
+    // coarse velocities used to compare bars, offsets are ignored
+    fn quantized(&self) -> Vec<i8> {
+        self.velocities()
+            .iter()
+            .map(|velocity| (velocity * 2.) as i8)
+            .collect()
+    }
+
+    // what bar equality and hashing compare, shape and quantized velocities,
+    // a lot smaller than the bar when deduplicating
+    pub fn dedup_key(&self) -> (Vec<usize>, Vec<i8>) {
+        (self.data.shape().to_vec(), self.quantized())
+    }
+}
+
+// bars are equal when their quantized velocities are, so near duplicates can be dropped
+impl PartialEq for Bar {
+    fn eq(&self, other: &Bar) -> bool {
+        self.dedup_key() == other.dedup_key()
+    }
+}
+
+impl Eq for Bar {}
+
+impl Hash for Bar {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.dedup_key().hash(state);
+    }
+}
+
+// one line per lane, one char per step: '.' rest, 'o' soft, 'x' medium, 'X' loud
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let beat_len = (self.number_of_steps() / 4).max(1);
+
+        for (lane, lane_events) in self.lanes().enumerate() {
+            let mut line = format!("{:>2} |", lane);
+
+            for (step, event) in lane_events.outer_iter().enumerate() {
+                if step > 0 && step % beat_len == 0 {
+                    line.push('|');
+                }
+                line.push(match event[VELOCITY] {
+                    v if v <= 0. => '.',
+                    v if v < 0.33 => 'o',
+                    v if v < 0.66 => 'x',
+                    _ => 'X',
+                });
+            }
+
+            writeln!(f, "{}|", line)?;
+        }
+
+        Ok(())
+    }
+}
+
+// (bars, steps, lanes, features) array, the shape is given so that no bars gives an empty array
+pub fn bars_to_array<B: Borrow<Bar>>(
+    bars: &[B],
+    number_of_steps: usize,
+    number_of_lanes: usize,
+) -> Result<Array<f32, Ix4>, MidiBeatError> {
+    let flattened_data: Vec<f32> = bars.iter().flat_map(|bar| bar.borrow().data.iter().cloned()).collect();
+
+    Ok(Array::from_shape_vec(
+        (bars.len(), number_of_steps, number_of_lanes, NUMBER_OF_FEATURES),
+        flattened_data,
+    )?)
+}
+
+pub fn array_to_bars(array: ArrayView<f32, Ix4>) -> Result<Vec<Bar>, MidiBeatError> {
+    array.outer_iter().map(Bar::from_view).collect()
+}
@@ -0,0 +1,209 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+#[cfg(feature = "serialization")]
+use serde::Serialize;
+
+use crate::datatypes::{DrumTrack, MappingSource};
+#[cfg(feature = "serialization")]
+use crate::error::MidiBeatError;
+use crate::explain::explain_track;
+use crate::map::Layout;
+
+// stored diagnostics are capped, counts keep going
+pub const MAX_DIAGNOSTICS: usize = 10_000;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+#[cfg_attr(feature = "serialization", serde(rename_all = "lowercase"))]
+pub enum Level {
+    Info,
+    Warning,
+    Error,
+}
+
+impl fmt::Display for Level {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Level::Info => write!(f, "info"),
+            Level::Warning => write!(f, "warning"),
+            Level::Error => write!(f, "error"),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct Diagnostic {
+    pub level: Level,
+    // short machine readable kind, used for counts
+    pub code: &'static str,
+    pub message: String,
+    pub file: Option<String>,
+    pub track: Option<usize>,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}] {}", self.level, self.code)?;
+        if let Some(file) = &self.file {
+            write!(f, " {}", file)?;
+        }
+        if let Some(track) = self.track {
+            write!(f, " track {}", track)?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+// collects what the library has to say instead of printing it
+pub struct Diagnostics {
+    pub diagnostics: Vec<Diagnostic>,
+    pub counts: BTreeMap<(Level, &'static str), u64>,
+    // current file, attached to every diagnostic
+    pub file: Option<String>,
+    // print diagnostics as they are recorded
+    pub echo: bool,
+    // store the per track diagnostics of record_tracks, they are counted either way
+    pub track_details: bool,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Diagnostics {
+        Diagnostics::new()
+    }
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics {
+            diagnostics: vec![],
+            counts: BTreeMap::new(),
+            file: None,
+            echo: false,
+            track_details: true,
+        }
+    }
+
+    pub fn set_file(&mut self, file: Option<String>) {
+        self.file = file;
+    }
+
+    pub fn record(&mut self, level: Level, code: &'static str, track: Option<usize>, message: String) {
+        *self.counts.entry((level, code)).or_insert(0) += 1;
+
+        let diagnostic = Diagnostic {
+            level,
+            code,
+            message,
+            file: self.file.clone(),
+            track,
+        };
+
+        if self.echo {
+            println!("{}", diagnostic);
+        }
+
+        if self.diagnostics.len() < MAX_DIAGNOSTICS {
+            self.diagnostics.push(diagnostic);
+        }
+    }
+
+    pub fn info(&mut self, code: &'static str, track: Option<usize>, message: String) {
+        self.record(Level::Info, code, track, message)
+    }
+
+    pub fn warn(&mut self, code: &'static str, track: Option<usize>, message: String) {
+        self.record(Level::Warning, code, track, message)
+    }
+
+    pub fn error(&mut self, code: &'static str, track: Option<usize>, message: String) {
+        self.record(Level::Error, code, track, message)
+    }
+
+    // counts a per track diagnostic, only stored with track_details
+    fn record_track(&mut self, level: Level, code: &'static str, track_index: usize, message: impl FnOnce() -> String) {
+        if self.track_details {
+            self.record(level, code, Some(track_index), message());
+        } else {
+            *self.counts.entry((level, code)).or_insert(0) += 1;
+        }
+    }
+
+    // fallback lanes, dropped keys and rejected tracks of the tracks of a file, for grids of the
+    // given resolution
+    pub fn record_tracks(&mut self, tracks: &Vec<DrumTrack>, layout: &Layout, resolution: usize) {
+        for (track_index, track) in tracks.iter().enumerate() {
+            let explanation = explain_track(track, track_index, layout, resolution);
+
+            for lane in explanation.lanes.iter() {
+                if let (Some(key), Some(MappingSource::Fallback)) = (lane.key, lane.source) {
+                    self.record_track(Level::Info, "fallback_lane", track_index, || {
+                        format!("key {} moved to lane {} ({} events)", key, lane.lane, lane.events)
+                    });
+                }
+            }
+
+            for dropped in explanation.dropped_keys.iter() {
+                let in_layout = layout.perc_map.iter().any(|group| group.contains(&dropped.key))
+                    || (!layout.merge_keys && layout.alt_map.contains_key(&dropped.key));
+                let code = if in_layout { "dropped_key" } else { "unmapped_key" };
+
+                self.record_track(Level::Warning, code, track_index, || {
+                    format!("key {} dropped ({} events): {}", dropped.key, dropped.events, dropped.reason)
+                });
+            }
+
+            if let Some(reason) = explanation.rejection {
+                self.record_track(Level::Warning, "rejected_track", track_index, || reason.to_owned());
+            }
+        }
+    }
+
+    pub fn count(&self, level: Level) -> u64 {
+        self.counts
+            .iter()
+            .filter(|((l, _), _)| *l == level)
+            .map(|(_, count)| count)
+            .sum()
+    }
+
+    pub fn summary(&self) -> String {
+        let mut lines: Vec<String> = vec![format!(
+            "diagnostics: {} errors, {} warnings, {} infos",
+            self.count(Level::Error),
+            self.count(Level::Warning),
+            self.count(Level::Info)
+        )];
+
+        self.counts.iter().rev().for_each(|((level, code), count)| {
+            lines.push(format!("  [{}] {}: {}", level, code, count));
+        });
+
+        lines.join("\n")
+    }
+
+    #[cfg(feature = "serialization")]
+    pub fn to_json(&self) -> Result<String, MidiBeatError> {
+        #[derive(Serialize)]
+        struct Count {
+            level: Level,
+            code: &'static str,
+            count: u64,
+        }
+
+        #[derive(Serialize)]
+        struct Report<'a> {
+            counts: Vec<Count>,
+            diagnostics: &'a Vec<Diagnostic>,
+        }
+
+        crate::serialization::to_json(&Report {
+            counts: self
+                .counts
+                .iter()
+                .map(|(&(level, code), &count)| Count { level, code, count })
+                .collect(),
+            diagnostics: &self.diagnostics,
+        })
+    }
+}
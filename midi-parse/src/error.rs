@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use ndarray::ShapeError;
+
+#[derive(Debug)]
+pub enum MidiBeatError {
+    Io(io::Error),
+    Midi(midly::Error),
+    // SMPTE timecode timing, bars can't be computed without a PPQN
+    TimecodeTiming,
+    EmptyTrack,
+    NoTracks,
+    // bar can't be split into steps
+    InvalidStepDuration {
+        ppqn: u16,
+        time_signature: (u8, u8, u8, u8),
+        resolution: usize,
+    },
+    // value which doesn't fit in its MIDI field
+    OutOfRange { field: &'static str, value: u32 },
+    Shape(ShapeError),
+    Csv(csv::Error),
+    Config(String),
+    // JSON or binary encoding of cached data
+    Serialization(String),
+    // error of a previous build, read from the cache
+    Cached(String),
+}
+
+impl fmt::Display for MidiBeatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MidiBeatError::Io(e) => write!(f, "IO error: {}", e),
+            MidiBeatError::Midi(e) => write!(f, "SMF parsing error: {}", e),
+            MidiBeatError::TimecodeTiming => write!(f, "timecode timing is not supported"),
+            MidiBeatError::EmptyTrack => write!(f, "track has no events"),
+            MidiBeatError::NoTracks => write!(f, "no tracks to process"),
+            MidiBeatError::InvalidStepDuration {
+                ppqn,
+                time_signature,
+                resolution,
+            } => write!(
+                f,
+                "bar of TS {}/{} with PPQN {} can't be split into {} steps",
+                time_signature.0, time_signature.1, ppqn, resolution
+            ),
+            MidiBeatError::OutOfRange { field, value } => {
+                write!(f, "value {} out of range for {}", value, field)
+            }
+            MidiBeatError::Shape(e) => write!(f, "Shape error: {}", e),
+            MidiBeatError::Csv(e) => write!(f, "CSV error: {}", e),
+            MidiBeatError::Config(message) => write!(f, "Config error: {}", message),
+            MidiBeatError::Serialization(message) => write!(f, "Serialization error: {}", message),
+            MidiBeatError::Cached(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for MidiBeatError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MidiBeatError::Io(e) => Some(e),
+            MidiBeatError::Midi(e) => Some(e),
+            MidiBeatError::Shape(e) => Some(e),
+            MidiBeatError::Csv(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for MidiBeatError {
+    fn from(e: io::Error) -> Self {
+        MidiBeatError::Io(e)
+    }
+}
+
+impl From<midly::Error> for MidiBeatError {
+    fn from(e: midly::Error) -> Self {
+        MidiBeatError::Midi(e)
+    }
+}
+
+impl From<ShapeError> for MidiBeatError {
+    fn from(e: ShapeError) -> Self {
+        MidiBeatError::Shape(e)
+    }
+}
+
+impl From<csv::Error> for MidiBeatError {
+    fn from(e: csv::Error) -> Self {
+        MidiBeatError::Csv(e)
+    }
+}
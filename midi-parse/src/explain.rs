@@ -0,0 +1,189 @@
+use std::fmt;
+
+#[cfg(feature = "serialization")]
+use serde::Serialize;
+
+use crate::datatypes::{DrumTrack, MappingSource};
+use crate::map::{get_track_rejection, Layout};
+
+// a key of the track with its number of events
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct KeyEvents {
+    pub key: u8,
+    pub events: usize,
+}
+
+// what happened to one lane of a track
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct LaneDecision {
+    pub lane: usize,
+    #[cfg_attr(feature = "serialization", serde(rename = "name"))]
+    pub lane_name: String,
+    pub key: Option<u8>,
+    pub source: Option<MappingSource>,
+    // events of the chosen key
+    pub events: usize,
+    // keys of the track listed in the lane group
+    pub candidates: Vec<KeyEvents>,
+}
+
+// a key of the track which did not get a lane
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct DroppedKey {
+    pub key: u8,
+    pub events: usize,
+    pub reason: &'static str,
+}
+
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct TrackExplanation {
+    pub track_index: usize,
+    pub time_signature: (u8, u8, u8, u8),
+    pub ppqn: u16,
+    pub layout: String,
+    // steps per bar of the grid the track is checked against
+    pub resolution: usize,
+    // every key of a lane group plays on the lane
+    pub merge_keys: bool,
+    pub key_footprint: Vec<KeyEvents>,
+    pub lanes: Vec<LaneDecision>,
+    pub dropped_keys: Vec<DroppedKey>,
+    // reason why the whole track is left out of the dataset, if any
+    pub rejection: Option<&'static str>,
+}
+
+// explanations of the tracks of a file
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct FileExplanation {
+    pub path: String,
+    pub tracks: Vec<TrackExplanation>,
+}
+
+pub fn explain_track(track: &DrumTrack, track_index: usize, layout: &Layout, resolution: usize) -> TrackExplanation {
+    let events = |key: u8| track.events.iter().filter(|e| e.key == key).count();
+
+    let key_footprint: Vec<KeyEvents> = track
+        .get_key_footprint()
+        .into_iter()
+        .map(|key| KeyEvents { key, events: events(key) })
+        .collect();
+
+    let lane_track = layout.get_lane_track(track);
+    let decisions = layout.get_track_perc_map_decisions(&lane_track);
+    // events played on the lane of a chosen key, every key of its group when keys are merged
+    let lane_events = |lane_key: u8| {
+        track
+            .events
+            .iter()
+            .filter(|e| layout.get_lane_key(e.key) == lane_key)
+            .count()
+    };
+
+    let lanes: Vec<LaneDecision> = decisions
+        .iter()
+        .enumerate()
+        .map(|(lane, decision)| LaneDecision {
+            lane,
+            lane_name: layout.lane_names.get(lane).cloned().unwrap_or_default(),
+            key: decision.map(|(key, _)| key),
+            source: decision.map(|(_, source)| source),
+            events: decision.map(|(key, _)| lane_events(key)).unwrap_or(0),
+            candidates: key_footprint
+                .iter()
+                .filter(|key_events| layout.perc_map[lane].contains(&key_events.key))
+                .cloned()
+                .collect(),
+        })
+        .collect();
+
+    let dropped_keys: Vec<DroppedKey> = key_footprint
+        .iter()
+        .filter(|key_events| {
+            let lane_key = layout.get_lane_key(key_events.key);
+            !decisions.iter().any(|decision| decision.map(|(k, _)| k) == Some(lane_key))
+        })
+        .map(|&KeyEvents { key, events }| {
+            let in_perc_map = layout.perc_map.iter().any(|group| group.contains(&key));
+            let in_alt_map = !layout.merge_keys && layout.alt_map.contains_key(&key);
+            let reason = match (in_perc_map, in_alt_map) {
+                (false, false) => "key not listed in the layout",
+                (true, false) => "lane taken by another key, no fallback lane",
+                (true, true) => "lane taken by another key, fallback lanes taken",
+                (false, true) => "no lane of its own, fallback lanes taken",
+            };
+            DroppedKey { key, events, reason }
+        })
+        .collect();
+
+    let track_perc_map: Vec<Option<u8>> = decisions.iter().map(|d| d.map(|(key, _)| key)).collect();
+
+    TrackExplanation {
+        track_index,
+        time_signature: track.time_signature,
+        ppqn: track.ppqn,
+        layout: layout.name.clone(),
+        resolution,
+        merge_keys: layout.merge_keys,
+        key_footprint,
+        lanes,
+        dropped_keys,
+        rejection: get_track_rejection(&lane_track, &track_perc_map, resolution),
+    }
+}
+
+pub fn explain_tracks(tracks: &Vec<DrumTrack>, layout: &Layout, resolution: usize) -> Vec<TrackExplanation> {
+    tracks
+        .iter()
+        .enumerate()
+        .map(|(track_index, track)| explain_track(track, track_index, layout, resolution))
+        .collect()
+}
+
+impl fmt::Display for TrackExplanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "track {} | TS {}/{} | PPQN {} | layout {} | resolution {}",
+            self.track_index, self.time_signature.0, self.time_signature.1, self.ppqn, self.layout, self.resolution
+        )?;
+
+        let footprint: Vec<String> = self
+            .key_footprint
+            .iter()
+            .map(|key_events| format!("{} ({})", key_events.key, key_events.events))
+            .collect();
+        writeln!(f, "  key footprint: {}", footprint.join(", "))?;
+
+        for lane in self.lanes.iter() {
+            match (lane.key, lane.source) {
+                (Some(key), Some(source)) => {
+                    let why = match source {
+                        MappingSource::Primary if self.merge_keys => "every key of the group".to_owned(),
+                        MappingSource::Primary => "primary, most played key of the group".to_owned(),
+                        MappingSource::Fallback => "fallback through the alt map".to_owned(),
+                    };
+                    writeln!(
+                        f,
+                        "  lane {} [{}]: key {} ({} events), {}",
+                        lane.lane, lane.lane_name, key, lane.events, why
+                    )?;
+                }
+                _ => writeln!(f, "  lane {} [{}]: empty", lane.lane, lane.lane_name)?,
+            }
+        }
+
+        for dropped in self.dropped_keys.iter() {
+            writeln!(
+                f,
+                "  dropped key {} ({} events): {}",
+                dropped.key, dropped.events, dropped.reason
+            )?;
+        }
+
+        match self.rejection {
+            Some(reason) => writeln!(f, "  track rejected: {}", reason),
+            None => writeln!(f, "  track kept"),
+        }
+    }
+}
@@ -0,0 +1,180 @@
+use std::path::Path;
+
+use midly::num::{u15, u24, u28, u4, u7};
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing, TrackEvent, TrackEventKind};
+use ndarray::{ArrayView, Ix4};
+
+use crate::datatypes::{array_to_bars, Bar, DEFAULT_TEMPO};
+use crate::error::MidiBeatError;
+use crate::map::{Layout, RESOLUTION};
+
+pub const DEFAULT_PPQN: u16 = 480;
+// GM drums
+pub const DRUM_CHANNEL: u8 = 9;
+// key of the lanes without any key in their layout
+const FALLBACK_KEY: u8 = 38;
+// order of the events of a same tick: meta events, then note offs so that repeated notes aren't cut
+pub const META_ORDER: u8 = 0;
+pub const NOTE_OFF_ORDER: u8 = 1;
+pub const NOTE_ON_ORDER: u8 = 2;
+
+// how grids are turned back into notes
+#[derive(Clone, Debug)]
+pub struct ExportSettings {
+    pub ppqn: u16,
+    // microseconds per quarter note
+    pub tempo: u32,
+    // (numerator, denominator), like 4/4
+    pub time_signature: (u8, u8),
+    // steps per bar of the grids
+    pub resolution: usize,
+    // ticks between note on and note off
+    pub note_length: u32,
+    // key played by each lane
+    pub keys: Vec<u8>,
+    pub channel: u8,
+}
+
+impl ExportSettings {
+    // 4/4 at 120 bpm, lanes play the first key of their layout lane
+    pub fn new(layout: &Layout) -> ExportSettings {
+        ExportSettings {
+            ppqn: DEFAULT_PPQN,
+            tempo: DEFAULT_TEMPO,
+            time_signature: (4, 4),
+            resolution: RESOLUTION,
+            note_length: DEFAULT_PPQN as u32 / 8,
+            keys: get_lane_keys(layout),
+            channel: DRUM_CHANNEL,
+        }
+    }
+
+    pub fn bar_ticks(&self) -> f32 {
+        self.time_signature.0 as f32 * self.ppqn as f32 * 4. / self.time_signature.1.max(1) as f32
+    }
+
+    pub fn step_ticks(&self) -> f32 {
+        self.bar_ticks() / self.resolution.max(1) as f32
+    }
+}
+
+// representative key of each lane, the first key of its perc map
+pub fn get_lane_keys(layout: &Layout) -> Vec<u8> {
+    layout
+        .perc_map
+        .iter()
+        .map(|keys| keys.first().cloned().unwrap_or(FALLBACK_KEY))
+        .collect()
+}
+
+// MIDI velocity of a normalized velocity, hits are at least 1
+pub fn to_midi_velocity(velocity: f32) -> u8 {
+    (velocity * 127.).round().clamp(1., 127.) as u8
+}
+
+// (tick, is note on, key, velocity) of the notes of consecutive grids, a grid can hold several bars
+pub fn get_notes(grids: &[Bar], settings: &ExportSettings) -> Result<Vec<(u32, bool, u8, u8)>, MidiBeatError> {
+    let step_ticks = settings.step_ticks();
+    let mut notes: Vec<(u32, bool, u8, u8)> = vec![];
+    let mut first_step: usize = 0;
+
+    for grid in grids.iter() {
+        if grid.number_of_lanes() > settings.keys.len() {
+            return Err(MidiBeatError::Config(format!(
+                "{} lanes but {} keys",
+                grid.number_of_lanes(),
+                settings.keys.len()
+            )));
+        }
+
+        for step in 0..grid.number_of_steps() {
+            for lane in 0..grid.number_of_lanes() {
+                if !grid.hit(step, lane) {
+                    continue;
+                }
+
+                let position = (first_step + step) as f32 + grid.offset(step, lane);
+                let tick = (position * step_ticks).round().max(0.) as u32;
+                let key = settings.keys[lane];
+                let velocity = to_midi_velocity(grid.velocity(step, lane));
+
+                notes.push((tick, true, key, velocity));
+                notes.push((tick + settings.note_length.max(1), false, key, 0));
+            }
+        }
+        first_step += grid.number_of_steps();
+    }
+
+    // note offs first on a same tick, so that repeated notes aren't cut
+    notes.sort_by_key(|&(tick, is_note_on, key, _)| (tick, is_note_on, key));
+    Ok(notes)
+}
+
+// single track SMF of consecutive grids, with tempo and time signature
+pub fn bars_to_smf(grids: &[Bar], settings: &ExportSettings) -> Result<Smf<'static>, MidiBeatError> {
+    let out_of_range = |field: &'static str, value: u32| MidiBeatError::OutOfRange { field, value };
+
+    let ppqn = u15::try_from(settings.ppqn).ok_or_else(|| out_of_range("ppqn", settings.ppqn as u32))?;
+    let tempo = u24::try_from(settings.tempo).ok_or_else(|| out_of_range("tempo", settings.tempo))?;
+    let channel = u4::try_from(settings.channel).ok_or_else(|| out_of_range("channel", settings.channel as u32))?;
+    // the denominator is stored as a power of 2
+    let denominator_power = (settings.time_signature.1.max(1) as f32).log2().round() as u8;
+
+    let mut events: Vec<(u32, u8, TrackEventKind<'static>)> = vec![
+        (0, META_ORDER, TrackEventKind::Meta(MetaMessage::Tempo(tempo))),
+        (
+            0,
+            META_ORDER,
+            TrackEventKind::Meta(MetaMessage::TimeSignature(settings.time_signature.0, denominator_power, 24, 8)),
+        ),
+    ];
+
+    for (tick, is_note_on, key, velocity) in get_notes(grids, settings)? {
+        let key = u7::try_from(key).ok_or_else(|| out_of_range("key", key as u32))?;
+        let vel = u7::new(velocity);
+        let (order, message) = if is_note_on {
+            (NOTE_ON_ORDER, MidiMessage::NoteOn { key, vel })
+        } else {
+            (NOTE_OFF_ORDER, MidiMessage::NoteOff { key, vel })
+        };
+        events.push((tick, order, TrackEventKind::Midi { channel, message }));
+    }
+
+    let mut smf = Smf::new(Header::new(Format::SingleTrack, Timing::Metrical(ppqn)));
+    smf.tracks.push(to_track(events)?);
+    Ok(smf)
+}
+
+// track of (tick, order, event) events, sorted by tick then order and delta encoded, ending with
+// an end of track. The sort is stable, events of a same tick and order keep their order
+pub fn to_track(mut events: Vec<(u32, u8, TrackEventKind<'_>)>) -> Result<Vec<TrackEvent<'_>>, MidiBeatError> {
+    events.sort_by_key(|(tick, order, _)| (*tick, *order));
+
+    let mut last_tick: u32 = 0;
+    let mut track = events
+        .into_iter()
+        .map(|(tick, _, kind)| {
+            let delta = tick - last_tick;
+            last_tick = tick;
+            u28::try_from(delta)
+                .map(|delta| TrackEvent { delta, kind })
+                .ok_or(MidiBeatError::OutOfRange { field: "delta", value: delta })
+        })
+        .collect::<Result<Vec<TrackEvent>, MidiBeatError>>()?;
+
+    track.push(TrackEvent {
+        delta: u28::new(0),
+        kind: TrackEventKind::Meta(MetaMessage::EndOfTrack),
+    });
+    Ok(track)
+}
+
+// rows of a (rows, steps, lanes, 2) array played one after the other
+pub fn array_to_smf(array: ArrayView<f32, Ix4>, settings: &ExportSettings) -> Result<Smf<'static>, MidiBeatError> {
+    bars_to_smf(&array_to_bars(array)?, settings)
+}
+
+pub fn write_midi<P: AsRef<Path>>(path: P, grids: &[Bar], settings: &ExportSettings) -> Result<(), MidiBeatError> {
+    bars_to_smf(grids, settings)?.save(path)?;
+    Ok(())
+}
@@ -0,0 +1,327 @@
+use std::convert::TryFrom;
+use std::fmt;
+
+#[cfg(feature = "serialization")]
+use serde::Serialize;
+
+use crate::datatypes::{Drum, DrumTrack};
+use crate::error::MidiBeatError;
+use crate::export::{get_notes, ExportSettings};
+use crate::map::{get_track_rejection, Layout};
+use crate::utils::get_time_stretch;
+
+// what a MIDI -> grid -> MIDI round trip kept of one lane
+#[derive(Clone, Debug, Default)]
+pub struct LaneFidelity {
+    pub events: u64,
+    pub kept: u64,
+    // sums over the kept events, in ticks and MIDI velocity
+    pub timing_error: f64,
+    pub velocity_error: f64,
+}
+
+// what a MIDI -> grid -> MIDI round trip kept of some tracks, events are note ons
+#[derive(Clone, Debug, Default)]
+pub struct Fidelity {
+    pub tracks: u64,
+    pub rejected_tracks: u64,
+    pub events: u64,
+    pub kept: u64,
+    // events lost with their whole track
+    pub rejected: u64,
+    // events of keys without a lane in the track perc map
+    pub unmapped: u64,
+    // events which lost their cell to another event of the lane
+    pub collisions: u64,
+    // events on the last step of the grid, which is never filled
+    pub last_step: u64,
+    // sums and maximums over the kept events
+    pub timing_error_ticks: f64,
+    pub timing_error_ms: f64,
+    pub max_timing_error_ms: f64,
+    pub velocity_error: f64,
+    pub max_velocity_error: u8,
+    pub lanes: Vec<LaneFidelity>,
+}
+
+impl Fidelity {
+    pub fn new(number_of_lanes: usize) -> Fidelity {
+        Fidelity {
+            lanes: vec![LaneFidelity::default(); number_of_lanes],
+            ..Default::default()
+        }
+    }
+
+    pub fn add(&mut self, other: &Fidelity) {
+        self.tracks += other.tracks;
+        self.rejected_tracks += other.rejected_tracks;
+        self.events += other.events;
+        self.kept += other.kept;
+        self.rejected += other.rejected;
+        self.unmapped += other.unmapped;
+        self.collisions += other.collisions;
+        self.last_step += other.last_step;
+        self.timing_error_ticks += other.timing_error_ticks;
+        self.timing_error_ms += other.timing_error_ms;
+        self.max_timing_error_ms = self.max_timing_error_ms.max(other.max_timing_error_ms);
+        self.velocity_error += other.velocity_error;
+        self.max_velocity_error = self.max_velocity_error.max(other.max_velocity_error);
+
+        if self.lanes.len() < other.lanes.len() {
+            self.lanes.resize(other.lanes.len(), LaneFidelity::default());
+        }
+        self.lanes.iter_mut().zip(other.lanes.iter()).for_each(|(lane, other)| {
+            lane.events += other.events;
+            lane.kept += other.kept;
+            lane.timing_error += other.timing_error;
+            lane.velocity_error += other.velocity_error;
+        });
+    }
+
+    // share of the events which didn't survive the round trip
+    pub fn loss(&self) -> f64 {
+        ratio((self.events - self.kept) as f64, self.events)
+    }
+
+    pub fn mean_timing_error_ticks(&self) -> f64 {
+        ratio(self.timing_error_ticks, self.kept)
+    }
+
+    pub fn mean_timing_error_ms(&self) -> f64 {
+        ratio(self.timing_error_ms, self.kept)
+    }
+
+    pub fn mean_velocity_error(&self) -> f64 {
+        ratio(self.velocity_error, self.kept)
+    }
+
+    // rates and means of the sums, what reports show
+    pub fn summary(&self, lane_names: &[String]) -> FidelitySummary {
+        FidelitySummary {
+            tracks: self.tracks,
+            rejected_tracks: self.rejected_tracks,
+            events: self.events,
+            kept: self.kept,
+            loss: self.loss(),
+            rejected: self.rejected,
+            unmapped: self.unmapped,
+            collisions: self.collisions,
+            last_step: self.last_step,
+            mean_timing_error_ticks: self.mean_timing_error_ticks(),
+            mean_timing_error_ms: self.mean_timing_error_ms(),
+            max_timing_error_ms: self.max_timing_error_ms,
+            mean_velocity_error: self.mean_velocity_error(),
+            max_velocity_error: self.max_velocity_error,
+            lanes: self
+                .lanes
+                .iter()
+                .enumerate()
+                .map(|(lane, fidelity)| LaneSummary {
+                    lane,
+                    name: lane_names.get(lane).cloned().unwrap_or_default(),
+                    events: fidelity.events,
+                    kept: fidelity.kept,
+                    loss: ratio((fidelity.events - fidelity.kept) as f64, fidelity.events),
+                    mean_timing_error_ticks: ratio(fidelity.timing_error, fidelity.kept),
+                    mean_velocity_error: ratio(fidelity.velocity_error, fidelity.kept),
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct LaneSummary {
+    pub lane: usize,
+    pub name: String,
+    pub events: u64,
+    pub kept: u64,
+    pub loss: f64,
+    pub mean_timing_error_ticks: f64,
+    pub mean_velocity_error: f64,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct FidelitySummary {
+    pub tracks: u64,
+    pub rejected_tracks: u64,
+    pub events: u64,
+    pub kept: u64,
+    pub loss: f64,
+    pub rejected: u64,
+    pub unmapped: u64,
+    pub collisions: u64,
+    pub last_step: u64,
+    pub mean_timing_error_ticks: f64,
+    pub mean_timing_error_ms: f64,
+    pub max_timing_error_ms: f64,
+    pub mean_velocity_error: f64,
+    pub max_velocity_error: u8,
+    pub lanes: Vec<LaneSummary>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct FileFidelity {
+    pub path: String,
+    pub fidelity: FidelitySummary,
+}
+
+// fidelity of a corpus for a grid spec
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct FidelityReport {
+    pub layout: String,
+    pub resolution: usize,
+    pub corpus: FidelitySummary,
+    pub files: Vec<FileFidelity>,
+}
+
+fn ratio(value: f64, count: u64) -> f64 {
+    if count > 0 {
+        value / count as f64
+    } else {
+        0.
+    }
+}
+
+// round trip of a track through the grid of a layout at a resolution, the lanes play the keys of
+// the track perc map so that only the quantization is measured
+pub fn measure_track(track: &DrumTrack, layout: &Layout, resolution: usize) -> Result<Fidelity, MidiBeatError> {
+    let mut fidelity = Fidelity::new(layout.number_of_lanes());
+    fidelity.tracks = 1;
+    fidelity.events = track.events.len() as u64;
+
+    // with merged keys, the hits of a lane on a same tick are collisions
+    let lane_track = layout.get_lane_track(track);
+    let track_perc_map = layout.get_track_perc_map(&lane_track);
+    if track.events.is_empty() || get_track_rejection(&lane_track, &track_perc_map, resolution).is_some() {
+        fidelity.rejected_tracks = 1;
+        fidelity.rejected = fidelity.events;
+        return Ok(fidelity);
+    }
+
+    let bars = lane_track.to_grid_with_resolution(&track_perc_map, resolution)?;
+    let step_ticks = lane_track.get_step_track_duration_with(resolution);
+    let last_step = bars.len() * resolution - 1;
+    // track ticks are stretched by the time signature
+    let ms_per_tick =
+        lane_track.tempo as f64 / 1000. / lane_track.ppqn.max(1) as f64 / get_time_stretch(lane_track.time_signature) as f64;
+
+    // the grid played back by the exporter in track ticks, as one quarter note of step_ticks
+    // ticks per step, each lane playing the key of the track perc map
+    let mut settings = ExportSettings::new(layout);
+    settings.ppqn = u16::try_from(step_ticks).map_err(|_| MidiBeatError::OutOfRange {
+        field: "step ticks",
+        value: step_ticks as u32,
+    })?;
+    settings.time_signature = (1, 4);
+    settings.resolution = 1;
+    settings.keys = track_perc_map.iter().map(|key| key.unwrap_or(0)).collect();
+    let notes = get_notes(&bars, &settings)?;
+
+    fidelity.unmapped = lane_track
+        .events
+        .iter()
+        .filter(|drum| !track_perc_map.contains(&Some(drum.key)))
+        .count() as u64;
+
+    for (lane, key) in track_perc_map.iter().enumerate() {
+        let key = match key {
+            Some(key) => *key,
+            None => continue,
+        };
+        let mut originals: Vec<&Drum> = lane_track.events.iter().filter(|drum| drum.key == key).collect();
+        originals.sort_by_key(|drum| drum.time);
+        let mut matched = vec![false; originals.len()];
+        let merged = track
+            .events
+            .iter()
+            .filter(|drum| layout.get_lane_key(drum.key) == key)
+            .count()
+            - originals.len();
+        fidelity.lanes[lane].events = (originals.len() + merged) as u64;
+        fidelity.collisions += merged as u64;
+
+        // hits and events are both sorted by tick, every hit holds the closest event after the
+        // events of the previous hits, the closest in velocity on ties. Distances to a hit only
+        // decrease then increase along the events, the events skipped were dropped
+        let mut next: usize = 0;
+        let hits = notes
+            .iter()
+            .filter(|&&(_, is_note_on, note_key, _)| is_note_on && note_key == key)
+            .map(|&(tick, _, _, velocity)| (tick, velocity));
+        for (tick, velocity) in hits {
+            if next >= originals.len() {
+                break;
+            }
+            let distance = |drum: &Drum| (drum.time.abs_diff(tick), drum.velocity.abs_diff(velocity));
+
+            let mut closest = next;
+            let mut index = next;
+            while index + 1 < originals.len() && distance(originals[index + 1]).0 <= distance(originals[index]).0 {
+                index += 1;
+                if distance(originals[index]) < distance(originals[closest]) {
+                    closest = index;
+                }
+            }
+            matched[closest] = true;
+            next = closest + 1;
+
+            let (timing_error, velocity_error) = distance(originals[closest]);
+            let timing_error = timing_error as f64;
+            fidelity.kept += 1;
+            fidelity.timing_error_ticks += timing_error;
+            fidelity.timing_error_ms += timing_error * ms_per_tick;
+            fidelity.max_timing_error_ms = fidelity.max_timing_error_ms.max(timing_error * ms_per_tick);
+            fidelity.velocity_error += velocity_error as f64;
+            fidelity.max_velocity_error = fidelity.max_velocity_error.max(velocity_error);
+            fidelity.lanes[lane].kept += 1;
+            fidelity.lanes[lane].timing_error += timing_error;
+            fidelity.lanes[lane].velocity_error += velocity_error as f64;
+        }
+
+        // what's left was dropped while filling the grid
+        for (drum, _) in originals.iter().zip(matched.iter()).filter(|(_, &matched)| !matched) {
+            if drum.time as usize / step_ticks >= last_step {
+                fidelity.last_step += 1;
+            } else {
+                fidelity.collisions += 1;
+            }
+        }
+    }
+
+    Ok(fidelity)
+}
+
+pub fn measure_tracks(tracks: &Vec<DrumTrack>, layout: &Layout, resolution: usize) -> Result<Fidelity, MidiBeatError> {
+    let mut fidelity = Fidelity::new(layout.number_of_lanes());
+    for track in tracks.iter() {
+        fidelity.add(&measure_track(track, layout, resolution)?);
+    }
+    Ok(fidelity)
+}
+
+impl fmt::Display for Fidelity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} events, {} kept ({:.2}% lost): {} rejected tracks ({} events), {} unmapped, {} collisions, {} last step | timing error {:.2} ticks, {:.2} ms (max {:.2} ms) | velocity error {:.2} (max {})",
+            self.events,
+            self.kept,
+            self.loss() * 100.,
+            self.rejected_tracks,
+            self.rejected,
+            self.unmapped,
+            self.collisions,
+            self.last_step,
+            self.mean_timing_error_ticks(),
+            self.mean_timing_error_ms(),
+            self.max_timing_error_ms,
+            self.mean_velocity_error(),
+            self.max_velocity_error
+        )
+    }
+}
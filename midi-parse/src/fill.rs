@@ -0,0 +1,156 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use ndarray::{Array, Ix1};
+
+use crate::datatypes::Bar;
+use crate::map::{Layout, TrackGrid};
+use crate::provenance::BarSource;
+
+// GM toms and crashes, used to find the lanes of layouts without explicit lane names
+const TOM_KEYS: [u8; 6] = [41, 43, 45, 47, 48, 50];
+const CRASH_KEYS: [u8; 4] = [49, 52, 55, 57];
+const GM_PERCUSSION_KEYS: RangeInclusive<u8> = 35..=81;
+
+// bars scoring at least this are fills
+pub const FILL_THRESHOLD: f32 = 0.5;
+pub const GROOVE_LABEL: i64 = 0;
+pub const FILL_LABEL: i64 = 1;
+
+// weights of the fill score components
+const TOM_WEIGHT: f32 = 0.35;
+const DEVIATION_WEIGHT: f32 = 0.35;
+const CRASH_WEIGHT: f32 = 0.15;
+const PHRASE_WEIGHT: f32 = 0.15;
+
+pub struct FillLanes {
+    pub toms: Vec<usize>,
+    pub crashes: Vec<usize>,
+}
+
+// lanes of a track playing a GM tom / crash, lanes playing keys outside of the GM percussion
+// range are toms / crashes when named after them. Lanes mixing cymbals like open_hh_crash
+// are crashes only in the tracks where a crash got the lane
+pub fn get_fill_lanes(layout: &Layout, track_perc_map: &[Option<u8>]) -> FillLanes {
+    let lanes_of = |name: &str, keys: &[u8]| -> Vec<usize> {
+        track_perc_map
+            .iter()
+            .enumerate()
+            .filter_map(|(lane, key)| key.map(|key| (lane, key)))
+            .filter(|&(lane, key)| {
+                let named = layout
+                    .lane_names
+                    .get(lane)
+                    .map(|lane_name| lane_name.contains(name))
+                    .unwrap_or(false);
+                keys.contains(&key) || (named && !GM_PERCUSSION_KEYS.contains(&key))
+            })
+            .map(|(lane, _)| lane)
+            .collect()
+    };
+
+    FillLanes {
+        toms: lanes_of("tom", &TOM_KEYS),
+        crashes: lanes_of("crash", &CRASH_KEYS),
+    }
+}
+
+// share of the hits played on tom lanes, full score from half of the hits
+fn tom_score(bar: &Bar, lanes: &FillLanes) -> f32 {
+    let tom_hits: usize = lanes
+        .toms
+        .iter()
+        .map(|&lane| (0..bar.number_of_steps()).filter(|&step| bar.hit(step, lane)).count())
+        .sum();
+
+    (2. * tom_hits as f32 / bar.number_of_hits().max(1) as f32).min(1.)
+}
+
+// crash on the downbeat of the next bar
+fn crash_score(next_bar: Option<&Bar>, lanes: &FillLanes) -> f32 {
+    match next_bar {
+        Some(next_bar) if lanes.crashes.iter().any(|&lane| next_bar.hit(0, lane)) => 1.,
+        _ => 0.,
+    }
+}
+
+// fills usually end 4 and 8 bar phrases
+fn phrase_score(bar_index: usize) -> f32 {
+    match bar_index + 1 {
+        position if position % 8 == 0 => 1.,
+        position if position % 4 == 0 => 0.5,
+        _ => 0.,
+    }
+}
+
+// fill score in [0, 1] of each bar of a track
+pub fn get_fill_scores(bars: &[Bar], lanes: &FillLanes) -> Vec<f32> {
+    // most played non silent bar of the track (quantized equality)
+    let mut pattern_counts: HashMap<&Bar, usize> = HashMap::new();
+    bars.iter()
+        .filter(|bar| !bar.is_silent())
+        .for_each(|bar| *pattern_counts.entry(bar).or_insert(0) += 1);
+    // earliest bar on ties, so that scores don't depend on the hash order
+    let dominant = bars
+        .iter()
+        .enumerate()
+        .filter(|(_, bar)| !bar.is_silent())
+        .max_by_key(|(bar_index, bar)| (pattern_counts[bar], Reverse(*bar_index)))
+        .map(|(_, bar)| bar);
+
+    bars.iter()
+        .enumerate()
+        .map(|(bar_index, bar)| {
+            if bar.is_silent() {
+                return 0.;
+            }
+
+            // hit grid distance to the dominant pattern
+            let deviation = dominant.map(|dominant| 1. - bar.hit_similarity(dominant)).unwrap_or(0.);
+
+            TOM_WEIGHT * tom_score(bar, lanes)
+                + DEVIATION_WEIGHT * deviation
+                + CRASH_WEIGHT * crash_score(bars.get(bar_index + 1), lanes)
+                + PHRASE_WEIGHT * phrase_score(bar_index)
+        })
+        .collect()
+}
+
+// fill scores of the bars of every grid of the pool, by index in track_pool
+pub fn get_track_pool_fill_scores(track_grids: &[TrackGrid], layout: &Layout) -> HashMap<usize, Vec<f32>> {
+    track_grids
+        .iter()
+        .map(|track_grid| {
+            let lanes = get_fill_lanes(layout, &track_grid.track_perc_map);
+            (track_grid.track_index, get_fill_scores(&track_grid.bars, &lanes))
+        })
+        .collect()
+}
+
+// fill score of each row, highest score of the bars offset..offset + span from the row source bar
+// (span 1 for single bars, window bars for windows, offset of context bars for pair targets)
+pub fn get_row_fill_scores(
+    fill_scores: &HashMap<usize, Vec<f32>>,
+    sources: &[BarSource],
+    offset: usize,
+    span: usize,
+) -> Array<f32, Ix1> {
+    sources
+        .iter()
+        .map(|source| {
+            let scores = match fill_scores.get(&source.track_index) {
+                Some(scores) => scores,
+                None => return 0.,
+            };
+
+            let start = (source.bar_index + offset).min(scores.len());
+            let end = (start + span).min(scores.len());
+            scores[start..end].iter().cloned().fold(0., f32::max)
+        })
+        .collect()
+}
+
+pub fn get_fill_labels(row_fill_scores: &Array<f32, Ix1>) -> Array<i64, Ix1> {
+    row_fill_scores.mapv(|score| if score >= FILL_THRESHOLD { FILL_LABEL } else { GROOVE_LABEL })
+}
@@ -0,0 +1,335 @@
+use std::fmt;
+use std::fs;
+
+use ndarray::{Array, Ix4};
+
+use crate::datatypes::{Bar, DrumTrack, DEFAULT_TIME_SIGNATURE};
+use crate::diagnostics::Diagnostics;
+use crate::error::MidiBeatError;
+use crate::map::{Layout, RESOLUTION};
+
+// bounds of the default filters, exclusive, the density ones are for RESOLUTION steps per bar
+pub const DENSITY_RANGE: (f32, f32) = (0.003, 0.3);
+pub const GRIDICITY_RANGE: (f32, f32) = (0.19, 0.9);
+
+// weight of each 32nd note of a beat pair in the gridicity kernel, downbeats weigh the most
+const GRIDICITY_WEIGHTS: [f32; 8] = [1.0, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1];
+// weight of the downbeats of the other lanes than the first one
+const GRIDICITY_OTHER_LANES_DOWNBEAT: f32 = 0.75;
+
+// decides whether a row of the dataset is kept, a row is one or several bars of a track
+pub trait BarFilter {
+    // diagnostic code of the filter counts
+    fn code(&self) -> &'static str;
+
+    fn keep(&self, bar: &Bar, track: &DrumTrack) -> bool;
+}
+
+// mean velocity of the row
+pub struct DensityFilter {
+    pub min: f32,
+    pub max: f32,
+}
+
+impl BarFilter for DensityFilter {
+    fn code(&self) -> &'static str {
+        "density_filter"
+    }
+
+    fn keep(&self, bar: &Bar, _track: &DrumTrack) -> bool {
+        let density = bar.density();
+        density > self.min && density < self.max
+    }
+}
+
+// how much the hits sit on strong steps, without offsets
+pub struct GridicityFilter {
+    pub min: f32,
+    pub max: f32,
+    pub steps_per_bar: usize,
+}
+
+impl GridicityFilter {
+    pub fn gridicity(&self, bar: &Bar) -> f32 {
+        let steps_per_bar = self.steps_per_bar.max(1);
+        let velocities = bar.velocities();
+        let offsets = bar.offsets();
+
+        // counts hits with velocities > 0.05
+        let hits = velocities.iter().filter(|&&velocity| velocity > 0.05).count() as f32;
+
+        let weighted: f32 = velocities
+            .indexed_iter()
+            .map(|((step, lane), velocity)| {
+                // position of the step in a 32 steps bar
+                let step_in_bar = (step % steps_per_bar) * RESOLUTION / steps_per_bar;
+                let weight = match (step_in_bar % GRIDICITY_WEIGHTS.len(), lane) {
+                    (0, lane) if lane > 0 => GRIDICITY_OTHER_LANES_DOWNBEAT,
+                    (index, _) => GRIDICITY_WEIGHTS[index],
+                };
+                // penalty for offsets
+                velocity * weight * (1.0 - offsets[[step, lane]].abs())
+            })
+            .sum();
+
+        weighted / hits
+    }
+}
+
+impl BarFilter for GridicityFilter {
+    fn code(&self) -> &'static str {
+        "gridicity_filter"
+    }
+
+    fn keep(&self, bar: &Bar, _track: &DrumTrack) -> bool {
+        let gridicity = self.gridicity(bar);
+        gridicity > self.min && gridicity < self.max
+    }
+}
+
+// every listed lane is hit at least once in the row
+pub struct LanePresenceFilter {
+    pub lanes: Vec<usize>,
+}
+
+impl BarFilter for LanePresenceFilter {
+    fn code(&self) -> &'static str {
+        "lane_filter"
+    }
+
+    fn keep(&self, bar: &Bar, _track: &DrumTrack) -> bool {
+        self.lanes
+            .iter()
+            .all(|&lane| lane < bar.number_of_lanes() && (0..bar.number_of_steps()).any(|step| bar.hit(step, lane)))
+    }
+}
+
+// time signature of the track, as found in the file
+pub struct MeterFilter {
+    // (numerator, denominator as a power of 2), the way tracks store them
+    pub time_signatures: Vec<(u8, u8)>,
+}
+
+impl BarFilter for MeterFilter {
+    fn code(&self) -> &'static str {
+        "meter_filter"
+    }
+
+    fn keep(&self, _bar: &Bar, track: &DrumTrack) -> bool {
+        // tracks without a time signature event are 4/4
+        let time_signature = if track.time_signature == DEFAULT_TIME_SIGNATURE {
+            (4, 2)
+        } else {
+            (track.time_signature.0, track.time_signature.1)
+        };
+        self.time_signatures.contains(&time_signature)
+    }
+}
+
+// tempo of the track in beats per minute, bounds included
+pub struct TempoFilter {
+    pub min_bpm: f32,
+    pub max_bpm: f32,
+}
+
+impl BarFilter for TempoFilter {
+    fn code(&self) -> &'static str {
+        "tempo_filter"
+    }
+
+    fn keep(&self, _bar: &Bar, track: &DrumTrack) -> bool {
+        let bpm = 60_000_000. / track.tempo.max(1) as f32;
+        bpm >= self.min_bpm && bpm <= self.max_bpm
+    }
+}
+
+// any other rule, for library users
+pub struct PredicateFilter<F: Fn(&Bar, &DrumTrack) -> bool> {
+    pub predicate: F,
+}
+
+impl<F: Fn(&Bar, &DrumTrack) -> bool> BarFilter for PredicateFilter<F> {
+    fn code(&self) -> &'static str {
+        "predicate_filter"
+    }
+
+    fn keep(&self, bar: &Bar, track: &DrumTrack) -> bool {
+        (self.predicate)(bar, track)
+    }
+}
+
+// density bounds for rows of steps_per_bar steps per bar, a same groove is denser on a coarser grid
+pub fn get_density_range(steps_per_bar: usize) -> (f32, f32) {
+    let scale = RESOLUTION as f32 / steps_per_bar.max(1) as f32;
+    (DENSITY_RANGE.0 * scale, DENSITY_RANGE.1 * scale)
+}
+
+// filters applied one after the other, each one only sees the rows kept by the previous ones
+#[derive(Default)]
+pub struct FilterPipeline {
+    // (spec, filter)
+    pub filters: Vec<(String, Box<dyn BarFilter>)>,
+}
+
+impl FilterPipeline {
+    pub fn new() -> FilterPipeline {
+        FilterPipeline { filters: vec![] }
+    }
+
+    // the density filter with its default range, what a dataset gets without any filter spec
+    pub fn default_filters(steps_per_bar: usize) -> FilterPipeline {
+        let (min, max) = get_density_range(steps_per_bar);
+        let mut pipeline = FilterPipeline::new();
+        pipeline.add(&format!("density={}:{}", min, max), Box::new(DensityFilter { min, max }));
+        pipeline
+    }
+
+    pub fn add(&mut self, spec: &str, filter: Box<dyn BarFilter>) {
+        self.filters.push((spec.to_owned(), filter));
+    }
+
+    // filter specs, like "density=0.003:0.3", "gridicity", "lanes=kick,snare", "meter=4/4,2/4"
+    // or "tempo=90:140", steps_per_bar is the resolution of the rows
+    pub fn parse(specs: &[String], layout: &Layout, steps_per_bar: usize) -> Result<FilterPipeline, MidiBeatError> {
+        let mut pipeline = FilterPipeline::new();
+        for spec in specs.iter() {
+            pipeline.add(spec, parse_filter(spec, layout, steps_per_bar)?);
+        }
+        Ok(pipeline)
+    }
+
+    // indexes of the rows kept by every filter, tracks are the track of each row, kept and
+    // rejected counts of each filter go to the diagnostics. Rows are read one at a time, rows
+    // which aren't bars are dropped with a warning. For pairs the rows are the contexts, the
+    // targets aren't filtered
+    pub fn kept_indices(
+        &self,
+        bars_array: &Array<f32, Ix4>,
+        tracks: &[&DrumTrack],
+        diagnostics: &mut Diagnostics,
+    ) -> Vec<usize> {
+        let mut kept: Vec<usize> = vec![];
+        let mut valid_rows: usize = 0;
+        // rows kept by each filter and the ones before it
+        let mut filter_kept: Vec<usize> = vec![0; self.filters.len()];
+
+        'rows: for (row, view) in bars_array.outer_iter().enumerate() {
+            let bar = match (Bar::from_view(view), tracks.get(row)) {
+                (Ok(bar), Some(_)) => bar,
+                (Err(e), _) => {
+                    diagnostics.warn("invalid_row", None, format!("row {}: {}", row, e));
+                    continue;
+                }
+                (_, None) => {
+                    diagnostics.warn("invalid_row", None, format!("row {}: no track", row));
+                    continue;
+                }
+            };
+            valid_rows += 1;
+
+            for (index, (_, filter)) in self.filters.iter().enumerate() {
+                if !filter.keep(&bar, tracks[row]) {
+                    continue 'rows;
+                }
+                filter_kept[index] += 1;
+            }
+            kept.push(row);
+        }
+
+        let mut before = valid_rows;
+        for ((spec, filter), &filter_kept) in self.filters.iter().zip(filter_kept.iter()) {
+            diagnostics.info(
+                filter.code(),
+                None,
+                format!("{} kept: {}, rejected: {}", spec, filter_kept, before - filter_kept),
+            );
+            before = filter_kept;
+        }
+        kept
+    }
+}
+
+impl fmt::Display for FilterPipeline {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let specs: Vec<&str> = self.filters.iter().map(|(spec, _)| spec.as_str()).collect();
+        write!(f, "{}", specs.join(" -> "))
+    }
+}
+
+pub fn parse_filter(spec: &str, layout: &Layout, steps_per_bar: usize) -> Result<Box<dyn BarFilter>, MidiBeatError> {
+    let error = |message: &str| MidiBeatError::Config(format!("filter '{}': {}", spec, message));
+    let number = |value: &str| value.trim().parse::<f32>().map_err(|_| error("expected a number"));
+    let lane = |value: &str| {
+        layout
+            .lane_index(value)
+            .ok_or_else(|| error(&format!("no lane {} in layout {}", value.trim(), layout.name)))
+    };
+
+    let mut parts = spec.splitn(2, '=');
+    let name = parts.next().unwrap_or("").trim();
+    let value = parts.next();
+    // min:max, defaults for missing bounds
+    let range = |default: (f32, f32)| -> Result<(f32, f32), MidiBeatError> {
+        match value {
+            Some(value) => {
+                let mut bounds = value.splitn(2, ':');
+                let min = bounds.next().filter(|min| !min.trim().is_empty()).map(number).transpose()?;
+                let max = bounds.next().filter(|max| !max.trim().is_empty()).map(number).transpose()?;
+                Ok((min.unwrap_or(default.0), max.unwrap_or(default.1)))
+            }
+            None => Ok(default),
+        }
+    };
+    let list = || -> Result<Vec<&str>, MidiBeatError> {
+        match value {
+            Some(value) if !value.trim().is_empty() => Ok(value.split(',').collect()),
+            _ => Err(error("expected a comma separated list")),
+        }
+    };
+
+    let filter: Box<dyn BarFilter> = match name {
+        "density" => {
+            let (min, max) = range(get_density_range(steps_per_bar))?;
+            Box::new(DensityFilter { min, max })
+        }
+        "gridicity" => {
+            let (min, max) = range(GRIDICITY_RANGE)?;
+            Box::new(GridicityFilter { min, max, steps_per_bar })
+        }
+        "lanes" => Box::new(LanePresenceFilter {
+            lanes: list()?.into_iter().map(lane).collect::<Result<Vec<usize>, MidiBeatError>>()?,
+        }),
+        "meter" => Box::new(MeterFilter {
+            time_signatures: list()?
+                .into_iter()
+                .map(|time_signature| {
+                    let mut values = time_signature.trim().splitn(2, '/');
+                    match (values.next().map(str::parse::<u8>), values.next().map(str::parse::<u8>)) {
+                        // the denominator is stored as a power of 2
+                        (Some(Ok(numerator)), Some(Ok(denominator))) if denominator.is_power_of_two() => {
+                            Ok((numerator, denominator.trailing_zeros() as u8))
+                        }
+                        _ => Err(error("expected time signatures like 4/4")),
+                    }
+                })
+                .collect::<Result<Vec<(u8, u8)>, MidiBeatError>>()?,
+        }),
+        "tempo" => {
+            let (min_bpm, max_bpm) = range((0., f32::MAX))?;
+            Box::new(TempoFilter { min_bpm, max_bpm })
+        }
+        _ => return Err(error("unknown filter")),
+    };
+
+    Ok(filter)
+}
+
+// filter specs of a config file, one per line, empty lines and lines starting with # are skipped
+pub fn read_filter_specs(path: &str) -> Result<Vec<String>, MidiBeatError> {
+    Ok(fs::read_to_string(path)?
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| line.to_owned())
+        .collect())
+}
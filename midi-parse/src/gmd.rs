@@ -0,0 +1,95 @@
+use ndarray::{Array, ArrayView, Axis, Ix3, Ix4};
+
+use crate::datatypes::{OFFSET, VELOCITY};
+use crate::diagnostics::Diagnostics;
+use crate::error::MidiBeatError;
+use crate::map::{process_track_pool_windows, Layout, TrackGrid};
+use crate::provenance::BarSource;
+
+// GrooVAE / Groove MIDI Dataset conventions
+pub const GMD_RESOLUTION: usize = 16;
+pub const GMD_WINDOW_BARS: [usize; 2] = [2, 4];
+// kick, snare, closed hh, open hh, low tom, mid tom, high tom, crash, ride
+pub const GMD_LANES: usize = 9;
+
+// separate hits, velocities and offsets tensors, shape (windows, bars * GMD_RESOLUTION, lanes)
+pub struct GmdTensors {
+    pub hits: Array<f32, Ix3>,
+    pub velocities: Array<f32, Ix3>,
+    pub offsets: Array<f32, Ix3>,
+}
+
+// windows of consecutive bars of a same track from the GMD_RESOLUTION grids of the pool,
+// shape (windows, bars * GMD_RESOLUTION, lanes, 2)
+// also returns the track and first bar each window comes from
+pub fn process_track_pool_gmd(
+    track_grids: &[TrackGrid],
+    layout: &Layout,
+    window_bars: usize,
+    hop: usize,
+    diagnostics: &mut Diagnostics,
+) -> Result<(Array<f32, Ix4>, Vec<BarSource>), MidiBeatError> {
+    check_gmd_layout(layout)?;
+    process_track_pool_windows(track_grids, layout, GMD_RESOLUTION, window_bars, hop, diagnostics)
+}
+
+// GMD tensors have the 9 lanes of the GrooVAE drum mapping
+pub fn check_gmd_layout(layout: &Layout) -> Result<(), MidiBeatError> {
+    if layout.number_of_lanes() != GMD_LANES {
+        return Err(MidiBeatError::Config(format!(
+            "GMD tensors need a {} lanes layout like gmd9, layout {} has {} lanes",
+            GMD_LANES,
+            layout.name,
+            layout.number_of_lanes()
+        )));
+    }
+    Ok(())
+}
+
+// offsets are kept in [-0.5, 0.5]: hits further than half a step from their step (shifted by a
+// collision or an augmentation) are moved onto the neighbouring step, the louder hit wins when it
+// is taken. Only hits at the window edges, without a neighbouring step, are clamped
+fn resolve_offsets(windows: &mut Array<f32, Ix4>) {
+    for mut window in windows.outer_iter_mut() {
+        let steps = window.shape()[0];
+        for lane in 0..window.shape()[1] {
+            for step in 0..steps {
+                let (velocity, offset) = (window[[step, lane, VELOCITY]], window[[step, lane, OFFSET]]);
+                if velocity <= 0. || offset.abs() <= 0.5 {
+                    continue;
+                }
+
+                let target = step as isize + offset.round() as isize;
+                if target < 0 || target >= steps as isize {
+                    window[[step, lane, OFFSET]] = offset.clamp(-0.5, 0.5);
+                    continue;
+                }
+
+                let target = target as usize;
+                if window[[target, lane, VELOCITY]] < velocity {
+                    window[[target, lane, VELOCITY]] = velocity;
+                    window[[target, lane, OFFSET]] = offset - (target as isize - step as isize) as f32;
+                }
+                window[[step, lane, VELOCITY]] = 0.;
+                window[[step, lane, OFFSET]] = 0.;
+            }
+        }
+    }
+}
+
+pub fn to_gmd_tensors(windows: ArrayView<f32, Ix4>) -> GmdTensors {
+    let mut windows = windows.to_owned();
+    resolve_offsets(&mut windows);
+
+    let velocities = windows.index_axis(Axis(3), VELOCITY).to_owned();
+    let hits = velocities.mapv(|velocity| if velocity > 0. { 1. } else { 0. });
+
+    // offsets of empty steps are 0
+    let offsets = &windows.index_axis(Axis(3), OFFSET) * &hits;
+
+    GmdTensors {
+        hits,
+        velocities,
+        offsets,
+    }
+}
@@ -0,0 +1,228 @@
+use std::collections::{BTreeMap, HashMap};
+
+use itertools::Itertools;
+
+use crate::datatypes::DrumTrack;
+use crate::map::{Layout, RESOLUTION};
+
+// number of fallback lanes written for each key of a learned layout
+const ALT_LANES: usize = 3;
+
+// rhythmic role of a key over the corpus
+#[derive(Clone)]
+pub struct KeyStats {
+    pub events: u64,
+    // number of tracks featuring the key
+    pub tracks: u64,
+    // number of bars of the tracks featuring the key
+    pub bars: u64,
+    // onset position distribution within the bar
+    pub onsets: [u64; RESOLUTION],
+}
+
+impl KeyStats {
+    fn new() -> KeyStats {
+        KeyStats {
+            events: 0,
+            tracks: 0,
+            bars: 0,
+            onsets: [0; RESOLUTION],
+        }
+    }
+
+    // events per bar of the tracks featuring the key
+    pub fn density(&self) -> f32 {
+        self.events as f32 / self.bars.max(1) as f32
+    }
+
+    pub fn onset_distribution(&self) -> Vec<f32> {
+        let total = self.events.max(1) as f32;
+        self.onsets.iter().map(|&count| count as f32 / total).collect()
+    }
+}
+
+// dataset wide key statistics, filled track by track
+#[derive(Default)]
+pub struct KeyStatsCollector {
+    pub keys: BTreeMap<u8, KeyStats>,
+    // number of tracks featuring both keys, smallest key first
+    pub co_occurrences: BTreeMap<(u8, u8), u64>,
+}
+
+impl KeyStatsCollector {
+    pub fn new() -> KeyStatsCollector {
+        KeyStatsCollector {
+            keys: BTreeMap::new(),
+            co_occurrences: BTreeMap::new(),
+        }
+    }
+
+    pub fn add_track(&mut self, track: &DrumTrack) {
+        let bar_tick_duration = track.get_bar_track_duration();
+
+        // skip tracks without a usable bar duration (timecode timing)
+        if bar_tick_duration < RESOLUTION || track.events.is_empty() {
+            return;
+        }
+
+        let number_of_bars = track.events.last().unwrap().time as u64 / bar_tick_duration as u64 + 1;
+        let key_footprint = track.get_key_footprint();
+
+        for key in key_footprint.iter() {
+            let stats = self.keys.entry(*key).or_insert_with(KeyStats::new);
+            stats.tracks += 1;
+            stats.bars += number_of_bars;
+        }
+
+        for event in track.events.iter() {
+            let position = (event.time as usize % bar_tick_duration) * RESOLUTION / bar_tick_duration;
+            let stats = self.keys.get_mut(&event.key).unwrap();
+            stats.events += 1;
+            stats.onsets[position] += 1;
+        }
+
+        for (key_a, key_b) in key_footprint.iter().tuple_combinations() {
+            *self.co_occurrences.entry((*key_a, *key_b)).or_insert(0) += 1;
+        }
+    }
+
+    // share of the tracks of the least frequent key also featuring the other key
+    fn co_occurrence_rate(&self, key_a: u8, key_b: u8) -> f32 {
+        let pair = if key_a < key_b { (key_a, key_b) } else { (key_b, key_a) };
+        let both = *self.co_occurrences.get(&pair).unwrap_or(&0) as f32;
+        let tracks = self.keys[&key_a].tracks.min(self.keys[&key_b].tracks).max(1) as f32;
+        both / tracks
+    }
+
+    // 0 for keys playing the same role and never played together, up to 1
+    fn distance(&self, key_a: u8, key_b: u8) -> f32 {
+        let (stats_a, stats_b) = (&self.keys[&key_a], &self.keys[&key_b]);
+
+        // half L1 distance between onset distributions, in [0, 1]
+        let role = stats_a
+            .onset_distribution()
+            .iter()
+            .zip(stats_b.onset_distribution().iter())
+            .map(|(a, b)| (a - b).abs())
+            .sum::<f32>()
+            / 2.;
+
+        // log density ratio, in [0, 1)
+        let density_ratio = (stats_a.density().max(1e-3) / stats_b.density().max(1e-3)).ln().abs();
+        let density = density_ratio / (1. + density_ratio);
+
+        // keys played together can't share a lane
+        let co_occurrence = self.co_occurrence_rate(key_a, key_b);
+
+        (role + density + co_occurrence) / 3.
+    }
+
+    // agglomerative clustering (average linkage) of the keys with at least min_events events,
+    // rarer keys only get fallback lanes, the closest lanes first
+    pub fn learn_layout(&self, name: &str, number_of_lanes: usize, min_events: u64) -> Layout {
+        let (keys, rare_keys): (Vec<u8>, Vec<u8>) = self
+            .keys
+            .keys()
+            .cloned()
+            .partition(|key| self.keys[key].events >= min_events);
+
+        let distances: HashMap<(u8, u8), f32> = keys
+            .iter()
+            .tuple_combinations()
+            .map(|(&key_a, &key_b)| ((key_a, key_b), self.distance(key_a, key_b)))
+            .collect();
+        let distance = |key_a: u8, key_b: u8| -> f32 {
+            if key_a == key_b {
+                0.
+            } else if key_a < key_b {
+                distances[&(key_a, key_b)]
+            } else {
+                distances[&(key_b, key_a)]
+            }
+        };
+        let linkage = |cluster_a: &Vec<u8>, cluster_b: &Vec<u8>| -> f32 {
+            let sum: f32 = cluster_a
+                .iter()
+                .cartesian_product(cluster_b.iter())
+                .map(|(&key_a, &key_b)| distance(key_a, key_b))
+                .sum();
+            sum / (cluster_a.len() * cluster_b.len()) as f32
+        };
+
+        let mut clusters: Vec<Vec<u8>> = keys.iter().map(|&key| vec![key]).collect();
+
+        while clusters.len() > number_of_lanes.max(1) {
+            let (index_a, index_b) = (0..clusters.len())
+                .tuple_combinations()
+                .min_by(|&(a, b), &(c, d)| {
+                    linkage(&clusters[a], &clusters[b])
+                        .partial_cmp(&linkage(&clusters[c], &clusters[d]))
+                        .unwrap()
+                })
+                .unwrap();
+
+            let merged = clusters.remove(index_b);
+            clusters[index_a].extend(merged);
+        }
+
+        // most played lanes first, most played keys first within a lane
+        let events = |key: &u8| self.keys[key].events;
+        let perc_map: Vec<Vec<u8>> = clusters
+            .into_iter()
+            .map(|cluster| cluster.into_iter().sorted_by(|a, b| events(b).cmp(&events(a))).collect::<Vec<u8>>())
+            .sorted_by(|a: &Vec<u8>, b: &Vec<u8>| {
+                let total = |cluster: &Vec<u8>| cluster.iter().map(events).sum::<u64>();
+                total(b).cmp(&total(a))
+            })
+            .collect();
+
+        // fallback lanes in order of average distance to the lane keys
+        let mut alt_map: HashMap<u8, Vec<usize>> = perc_map
+            .iter()
+            .enumerate()
+            .flat_map(|(lane, cluster)| cluster.iter().map(move |key| (lane, *key)))
+            .map(|(lane, key)| {
+                let groups: Vec<usize> = perc_map
+                    .iter()
+                    .enumerate()
+                    .filter(|(other_lane, _)| *other_lane != lane)
+                    .map(|(other_lane, cluster)| (other_lane, linkage(&vec![key], cluster)))
+                    .sorted_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                    .take(ALT_LANES)
+                    .map(|(other_lane, _)| other_lane)
+                    .collect();
+                (key, groups)
+            })
+            .collect();
+
+        // rare keys aren't part of the distance cache
+        alt_map.extend(rare_keys.iter().filter(|_| !perc_map.is_empty()).map(|&key| {
+            let groups: Vec<usize> = perc_map
+                .iter()
+                .enumerate()
+                .map(|(lane, cluster)| {
+                    let sum: f32 = cluster.iter().map(|&lane_key| self.distance(key, lane_key)).sum();
+                    (lane, sum / cluster.len() as f32)
+                })
+                .sorted_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+                .take(ALT_LANES)
+                .map(|(lane, _)| lane)
+                .collect();
+            (key, groups)
+        }));
+
+        let lane_names: Vec<String> = perc_map
+            .iter()
+            .enumerate()
+            .map(|(lane, cluster)| format!("lane_{}_key_{}", lane, cluster[0]))
+            .collect();
+
+        Layout {
+            name: name.to_owned(),
+            lane_names,
+            perc_map,
+            alt_map,
+            merge_keys: false,
+        }
+    }
+}
@@ -1,5 +1,29 @@
+// tracks, bars and layouts are passed around as &Vec and nested tuples across the crate
+#![allow(clippy::ptr_arg, clippy::type_complexity)]
+
+pub mod augment;
+#[cfg(feature = "serialization")]
+pub mod cache;
 pub mod datatypes;
+pub mod diagnostics;
+pub mod error;
+pub mod explain;
+pub mod export;
+pub mod fidelity;
+pub mod filter;
+pub mod fill;
+pub mod gmd;
+pub mod learn;
+pub mod manifest;
 pub mod map;
+pub mod metadata;
 pub mod parse;
+pub mod provenance;
+#[cfg(feature = "serialization")]
+pub mod reader;
+pub mod sections;
+#[cfg(feature = "serialization")]
+pub mod serialization;
+pub mod split;
 pub mod stats;
 pub mod utils;
\ No newline at end of file
@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
+
+pub const MANIFEST_VERSION: u32 = 1;
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct ManifestArray {
+    pub name: String,
+    // safetensors names: F32, I64 or U8
+    pub dtype: String,
+    pub shape: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct ManifestShard {
+    // relative to the manifest directory
+    pub path: String,
+    pub split: Option<String>,
+    // index of the first row of the shard in the whole dataset
+    pub start_row: usize,
+    pub rows: usize,
+    // uncompressed size of the arrays
+    pub bytes: u64,
+    pub arrays: Vec<ManifestArray>,
+    pub vocabularies: Vec<String>,
+}
+
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct ManifestInput {
+    pub path: String,
+    pub hash: String,
+}
+
+// index of a sharded dataset
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Manifest {
+    pub version: u32,
+    pub format: String,
+    pub rows: usize,
+    // steps per bar
+    pub resolution: usize,
+    pub layout: String,
+    pub lane_names: Vec<String>,
+    // features of the grid arrays, like x.velocity and x.offset, or hits for GMD tensors
+    pub features: Vec<String>,
+    // parser options the dataset was built with
+    pub config: BTreeMap<String, String>,
+    pub inputs: Vec<ManifestInput>,
+    pub shards: Vec<ManifestShard>,
+}
@@ -1,30 +1,47 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
 use std::collections::HashMap;
+use std::path::Path;
 
-use crate::datatypes::DrumTrack;
+use crate::datatypes::{bars_to_array, Bar, Drum, DrumTrack, MappingSource};
+use crate::diagnostics::Diagnostics;
+use crate::error::MidiBeatError;
+use crate::provenance::BarSource;
+
+#[cfg(feature = "serialization")]
+use serde::{Deserialize, Serialize};
 use drawille::Canvas;
 use itertools::Itertools;
-use ndarray::{Array, ArrayView, Ix1, Ix2, Ix3, Ix4, ShapeError};
+use ndarray::{concatenate, Array, ArrayView, Axis, Ix1, Ix2, Ix3, Ix4};
 
 pub const RESOLUTION: usize = 32;
 pub const NUMBER_OF_TRACKS: usize = 8;
+pub const NUMBER_OF_EXTENDED_TRACKS: usize = 10;
 pub const THRESH_NON_EMPTY_TRACKS: usize = 0; // 0 means
+// supported number of bars per window, 1 being isolated bars
+pub const WINDOW_BARS: [usize; 4] = [1, 2, 4, 8];
+
+// GM2 percussion keys outside of the GM1 range (35 - 81)
+// 27 high Q, 28 slap, 29 scratch push, 30 scratch pull, 31 sticks,
+// 32 square click, 33 metronome click, 34 metronome bell,
+// 82 shaker, 83 jingle bell, 84 belltree, 85 castanets, 86 mute surdo, 87 open surdo
 
 pub fn get_perc_map() -> [Vec<u8>; NUMBER_OF_TRACKS] {
     [
         // KICK
         vec![35, 36],
         // SNARE / RIMS
-        vec![37, 38, 39, 40],
+        vec![37, 38, 39, 40, 28, 31],
 
         // TOMS
-        vec![41, 45, 61, 64, 66], // low tom
-        vec![47, 48, 58, 60, 63, 68, 74, 77, 78, 79], // mid tom
-        vec![43, 50, 54, 56, 62, 65, 67, 69, 70, 71, 72, 73, 75, 76, 81], // high tom
+        vec![41, 45, 61, 64, 66, 86, 87], // low tom
+        vec![47, 48, 58, 60, 63, 68, 74, 77, 78, 79, 29, 30], // mid tom
+        vec![43, 50, 54, 56, 62, 65, 67, 69, 70, 71, 72, 73, 75, 76, 81, 27, 32, 33, 85], // high tom
 
         // HH / CYMB
-        vec![42, 44],  // muted hh
-        vec![46, 49, 55, 57], // open hat / splash / crash
-        vec![51, 52, 53, 59, 80], // ride
+        vec![42, 44, 82],  // muted hh
+        vec![46, 49, 55, 57, 83, 84], // open hat / splash / crash
+        vec![51, 52, 53, 59, 80, 34], // ride
     ]
 }
 
@@ -40,6 +57,8 @@ pub fn get_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
     rmap.insert(38, vec![4, 3]);
     rmap.insert(39, vec![4, 3]);
     rmap.insert(40, vec![4, 3]);
+    rmap.insert(28, vec![4, 3]);
+    rmap.insert(31, vec![4, 3]);
 
     // low percs can go into the kick group, or mid and high perc group
     rmap.insert(41, vec![0, 3, 4]);
@@ -47,6 +66,8 @@ pub fn get_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
     rmap.insert(61, vec![0, 3, 4]);
     rmap.insert(64, vec![0, 3, 4]);
     rmap.insert(66, vec![0, 3, 4]);
+    rmap.insert(86, vec![0, 3, 4]);
+    rmap.insert(87, vec![0, 3, 4]);
 
     // mid percs can go into the snare group, or low and high perc group
     rmap.insert(47, vec![4, 2, 1]);
@@ -59,6 +80,8 @@ pub fn get_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
     rmap.insert(77, vec![4, 2, 1]);
     rmap.insert(78, vec![4, 2, 1]);
     rmap.insert(79, vec![4, 2, 1]);
+    rmap.insert(29, vec![4, 2, 1]);
+    rmap.insert(30, vec![4, 2, 1]);
 
     // high percs can go into the snare group, or low and mid perc group
     rmap.insert(43, vec![3, 2, 1]);
@@ -76,16 +99,23 @@ pub fn get_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
     rmap.insert(75, vec![3, 2, 1]);
     rmap.insert(76, vec![3, 2, 1]);
     rmap.insert(81, vec![3, 2, 1]);
+    rmap.insert(27, vec![3, 2, 1]);
+    rmap.insert(32, vec![3, 2, 1]);
+    rmap.insert(33, vec![3, 2, 1]);
+    rmap.insert(85, vec![3, 2, 1]);
 
     // muted hh are flexible
     rmap.insert(42, vec![6, 7, 4, 3, 1]);
     rmap.insert(44, vec![6, 7, 4, 3, 1]);
+    rmap.insert(82, vec![6, 7, 4, 3, 1]);
 
     // open hat / splash / crash are flexible
     rmap.insert(46, vec![5, 7, 4, 3, 1]);
     rmap.insert(49, vec![5, 7, 4, 3, 1]);
     rmap.insert(55, vec![5, 7, 4, 3, 1]);
     rmap.insert(57, vec![5, 7, 4, 3, 1]);
+    rmap.insert(83, vec![5, 7, 4, 3, 1]);
+    rmap.insert(84, vec![5, 7, 4, 3, 1]);
 
     // ridoids is flexible
     rmap.insert(51, vec![5, 6, 4, 3, 1]);
@@ -93,75 +123,729 @@ pub fn get_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
     rmap.insert(53, vec![5, 6, 4, 3, 1]);
     rmap.insert(59, vec![5, 6, 4, 3, 1]);
     rmap.insert(80, vec![5, 6, 4, 3, 1]);
+    rmap.insert(34, vec![5, 6, 4, 3, 1]);
 
 
     rmap
 }
 
-pub fn process_track_pool(track_pool: &Vec<DrumTrack>) -> Result<Array<f32, Ix4>, ShapeError> {
-    let flattened_bars: Vec<[[[f32; 2]; NUMBER_OF_TRACKS]; RESOLUTION]> = track_pool
-        .into_iter()
-        .map(|track| {
-            return track;
+// alternative layout: toms are only toms, latin / hand percs and
+// shakers / small percs / clicks get their own lanes
+pub fn get_extended_perc_map() -> [Vec<u8>; NUMBER_OF_EXTENDED_TRACKS] {
+    [
+        // KICK
+        vec![35, 36],
+        // SNARE / RIMS
+        vec![37, 38, 39, 40, 28, 31],
+
+        // TOMS
+        vec![41, 43], // low tom
+        vec![45, 47], // mid tom
+        vec![48, 50], // high tom
+
+        // HH / CYMB
+        vec![42, 44],  // muted hh
+        vec![46, 49, 55, 57, 52], // open hat / splash / crash
+        vec![51, 53, 59], // ride
+
+        // HAND PERCS: bongos, congas, timbales, cowbell, agogos, woodblocks, cuicas, surdos
+        vec![60, 61, 62, 63, 64, 65, 66, 56, 67, 68, 76, 77, 78, 79, 86, 87],
+        // SMALL PERCS: tambourine, shakers, bells, whistles, guiros, claves, triangles, clicks
+        vec![54, 69, 70, 82, 83, 84, 85, 58, 71, 72, 73, 74, 75, 80, 81, 27, 29, 30, 32, 33, 34],
+    ]
+}
+
+pub fn get_extended_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
+    let mut rmap: HashMap<u8, Vec<usize>> = HashMap::new();
+
+    // kicks can go into the low tom group
+    for key in [35, 36].iter() {
+        rmap.insert(*key, vec![2]);
+    }
+
+    // snares can go into the hand perc group, or small perc group
+    for key in [37, 38, 39, 40, 28, 31].iter() {
+        rmap.insert(*key, vec![8, 9]);
+    }
+
+    // toms can go into any other tom group, then the hand perc group
+    for key in [41, 43].iter() {
+        rmap.insert(*key, vec![3, 4, 8]);
+    }
+    for key in [45, 47].iter() {
+        rmap.insert(*key, vec![2, 4, 8]);
+    }
+    for key in [48, 50].iter() {
+        rmap.insert(*key, vec![3, 2, 8]);
+    }
+
+    // hats and cymbals are flexible
+    for key in [42, 44].iter() {
+        rmap.insert(*key, vec![6, 7, 9]);
+    }
+    for key in [46, 49, 55, 57, 52].iter() {
+        rmap.insert(*key, vec![5, 7, 9]);
+    }
+    for key in [51, 53, 59].iter() {
+        rmap.insert(*key, vec![5, 6, 9]);
+    }
+
+    // hand percs can go into the toms, then the small perc group
+    for key in [60, 61, 62, 63, 64, 65, 66, 56, 67, 68, 76, 77, 78, 79, 86, 87].iter() {
+        rmap.insert(*key, vec![4, 3, 2, 9]);
+    }
+
+    // small percs can go into the hats, then the hand perc group
+    for key in [54, 69, 70, 82, 83, 84, 85, 58, 71, 72, 73, 74, 75, 80, 81, 27, 29, 30, 32, 33, 34].iter() {
+        rmap.insert(*key, vec![5, 7, 8]);
+    }
+
+    rmap
+}
+
+// GMD / Magenta 9 classes drum mapping
+pub fn get_gmd_perc_map() -> [Vec<u8>; 9] {
+    [
+        // KICK
+        vec![36, 35],
+        // SNARE
+        vec![38, 27, 28, 31, 32, 33, 34, 37, 39, 40, 56, 65, 66, 75, 85],
+        // CLOSED HH
+        vec![42, 44, 54, 68, 69, 70, 71, 73, 78, 80, 22],
+        // OPEN HH
+        vec![46, 67, 72, 74, 79, 81, 26],
+        // LOW TOM
+        vec![45, 29, 41, 43, 61, 64, 84],
+        // MID TOM
+        vec![48, 47, 60, 63, 77, 86, 87],
+        // HIGH TOM
+        vec![50, 30, 62, 76, 83],
+        // CRASH
+        vec![49, 55, 57, 58],
+        // RIDE
+        vec![51, 52, 53, 59, 82],
+    ]
+}
+
+pub fn get_gmd_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
+    let perc_map = get_gmd_perc_map();
+    let mut rmap: HashMap<u8, Vec<usize>> = HashMap::new();
+
+    // groups in order of preference for each lane
+    let alt_groups: [Vec<usize>; 9] = [
+        vec![4],       // kick can go into the low tom group
+        vec![6, 5],    // snare can go into the high and mid tom group
+        vec![3, 8],    // closed hh can go into the open hh and ride group
+        vec![2, 7, 8], // open hh can go into closed hh, crash and ride group
+        vec![5, 6, 0], // low tom can go into the other toms, or the kick group
+        vec![4, 6],    // mid tom can go into the low and high tom group
+        vec![5, 4],    // high tom can go into the mid and low tom group
+        vec![3, 8],    // crash can go into the open hh and ride group
+        vec![7, 2, 3], // ride can go into the crash and hh groups
+    ];
+
+    perc_map.iter().zip(alt_groups.iter()).for_each(|(perc_group, groups)| {
+        perc_group.iter().for_each(|key| {
+            rmap.insert(*key, groups.clone());
         })
-        .map(|track| (track, track.get_track_perc_map()))
-
-        // filter tracks with less than 1 mapped percs
-        .filter(|(_, track_perc_map)| {
-            let percs_number = track_perc_map
-                .into_iter()
-                .filter(|option_key| match option_key {
-                    Some(_) => true,
-                    None => false,
-                })
-                .count();
-
-            percs_number > THRESH_NON_EMPTY_TRACKS
+    });
+
+    rmap
+}
+
+// Roland TD-11 22 classes drum mapping, as recorded in the Groove MIDI Dataset
+pub fn get_td11_perc_map() -> [Vec<u8>; 22] {
+    [
+        // KICK
+        vec![36, 35],
+        // SNARE: head, rim, x-stick
+        vec![38, 39, 28],
+        vec![40],
+        vec![37, 31],
+        // TOMS: tom 1 head / rim, tom 2 head / rim, tom 3 head / rim
+        vec![48],
+        vec![50],
+        vec![45],
+        vec![47],
+        vec![43, 41],
+        vec![58],
+        // HH: open bow / edge, closed bow / edge, pedal
+        vec![46],
+        vec![26],
+        vec![42],
+        vec![22],
+        vec![44],
+        // CYMB: crash 1 bow / edge, crash 2 bow / edge, ride bow / edge / bell
+        vec![49],
+        vec![55],
+        vec![57],
+        vec![52],
+        vec![51],
+        vec![59],
+        vec![53],
+    ]
+}
+
+pub fn get_td11_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
+    let mut rmap: HashMap<u8, Vec<usize>> = HashMap::new();
+
+    // extra kick can go into the tom 3 group
+    rmap.insert(35, vec![8]);
+
+    // extra snares can go into the rim and x-stick groups
+    rmap.insert(39, vec![2, 3]);
+    rmap.insert(28, vec![3, 2]);
+    rmap.insert(31, vec![2]);
+
+    // extra floor tom can go into the tom 2 group
+    rmap.insert(41, vec![6, 9]);
+
+    // GM percs with no TD-11 pad can go into the rims and edges
+    rmap.insert(54, vec![13, 11]);
+    rmap.insert(56, vec![5, 7]);
+    rmap.insert(82, vec![13, 11]);
+
+    rmap
+}
+
+// minimal kick / snare / hat mapping
+pub fn get_minimal_perc_map() -> [Vec<u8>; 3] {
+    [
+        // KICK
+        vec![35, 36],
+        // SNARE / RIMS / CLAPS
+        vec![38, 40, 37, 39, 28, 31],
+        // HH / RIDE / SHAKERS
+        vec![42, 44, 46, 51, 59, 53, 82, 54, 69, 70],
+    ]
+}
+
+pub fn get_minimal_alt_reverse_perc_map() -> HashMap<u8, Vec<usize>> {
+    let mut rmap: HashMap<u8, Vec<usize>> = HashMap::new();
+
+    // low toms and surdos can go into the kick group
+    for key in [41, 43, 45, 86, 87].iter() {
+        rmap.insert(*key, vec![0]);
+    }
+
+    // high toms and woodblocks can go into the snare group
+    for key in [47, 48, 50, 76, 77].iter() {
+        rmap.insert(*key, vec![1]);
+    }
+
+    // crashes and bells can go into the hh group
+    for key in [49, 52, 55, 57, 56, 80, 81].iter() {
+        rmap.insert(*key, vec![2]);
+    }
+
+    rmap
+}
+
+// a named set of lanes with its primary and fallback tables
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct Layout {
+    pub name: String,
+    pub lane_names: Vec<String>,
+    pub perc_map: Vec<Vec<u8>>,
+    pub alt_map: HashMap<u8, Vec<usize>>,
+    // every key of a lane group plays on the lane, like the GMD / Magenta mapping, instead of only
+    // the most played key of the group with the others sent to their fallback lanes.
+    // The alt map isn't used
+    #[cfg_attr(feature = "serialization", serde(default))]
+    pub merge_keys: bool,
+}
+
+impl Layout {
+    pub fn number_of_lanes(&self) -> usize {
+        self.perc_map.len()
+    }
+
+    // key a track event is gridded with, the first key of its lane group when keys are merged
+    pub fn get_lane_key(&self, key: u8) -> u8 {
+        if !self.merge_keys {
+            return key;
+        }
+        self.perc_map
+            .iter()
+            .find(|group| group.contains(&key))
+            .and_then(|group| group.first())
+            .cloned()
+            .unwrap_or(key)
+    }
+
+    // the track as the layout grids it, when keys are merged the events play the lane keys and
+    // the loudest hit wins when keys of a lane hit on a same tick
+    pub fn get_lane_track<'a>(&self, track: &'a DrumTrack) -> Cow<'a, DrumTrack> {
+        if !self.merge_keys {
+            return Cow::Borrowed(track);
+        }
+
+        let mut events: Vec<Drum> = track
+            .events
+            .iter()
+            .map(|drum| Drum {
+                key: self.get_lane_key(drum.key),
+                ..*drum
+            })
+            .collect();
+        events.sort_by_key(|drum| (drum.time, drum.key, Reverse(drum.velocity)));
+        events.dedup_by_key(|drum| (drum.time, drum.key));
+
+        let mut lane_track = track.clone();
+        lane_track.events = events;
+        Cow::Owned(lane_track)
+    }
+
+    // key played by each lane of a lane track (see get_lane_track)
+    pub fn get_track_perc_map(&self, lane_track: &DrumTrack) -> Vec<Option<u8>> {
+        self.get_track_perc_map_decisions(lane_track)
+            .into_iter()
+            .map(|decision| decision.map(|(key, _)| key))
+            .collect()
+    }
+
+    pub fn get_track_perc_map_decisions(&self, lane_track: &DrumTrack) -> Vec<Option<(u8, MappingSource)>> {
+        if self.merge_keys {
+            lane_track.get_track_perc_map_decisions(&self.perc_map, &HashMap::new())
+        } else {
+            lane_track.get_track_perc_map_decisions(&self.perc_map, &self.alt_map)
+        }
+    }
+
+    // lane of a lane name or index
+    pub fn lane_index(&self, name_or_index: &str) -> Option<usize> {
+        let name_or_index = name_or_index.trim();
+        self.lane_names
+            .iter()
+            .position(|lane_name| lane_name == name_or_index)
+            .or_else(|| name_or_index.parse::<usize>().ok().filter(|&lane| lane < self.number_of_lanes()))
+    }
+
+    // layout CSV, one row per key: lane index (empty for fallback only keys), lane name,
+    // key, fallback lanes separated by spaces. Keys of a lane are listed in order of preference
+    pub fn from_csv(csv_path: &str) -> Result<Layout, MidiBeatError> {
+        let mut reader = csv::Reader::from_path(csv_path)?;
+
+        let mut lane_names: Vec<String> = vec![];
+        let mut perc_map: Vec<Vec<u8>> = vec![];
+        let mut alt_map: HashMap<u8, Vec<usize>> = HashMap::new();
+
+        for record in reader.records() {
+            let record = record?;
+            let key: u8 = parse_field(record.get(2), "key")?;
+
+            let lane = record.get(0).unwrap_or("").trim();
+            if !lane.is_empty() {
+                let lane: usize = parse_field(Some(lane), "lane")?;
+                if lane > perc_map.len() {
+                    return Err(MidiBeatError::Config(format!(
+                        "lane {} is listed before lane {}",
+                        lane,
+                        perc_map.len()
+                    )));
+                }
+                if lane == perc_map.len() {
+                    lane_names.push(record.get(1).unwrap_or("").trim().to_owned());
+                    perc_map.push(vec![]);
+                }
+                perc_map[lane].push(key);
+            }
+
+            let groups: Vec<usize> = record
+                .get(3)
+                .unwrap_or("")
+                .split_whitespace()
+                .map(|group| parse_field(Some(group), "alt"))
+                .collect::<Result<Vec<usize>, MidiBeatError>>()?;
+            if !groups.is_empty() {
+                alt_map.insert(key, groups);
+            }
+        }
+
+        let name = Path::new(csv_path)
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_else(|| csv_path.to_owned());
+
+        Ok(Layout {
+            name,
+            lane_names,
+            perc_map,
+            alt_map,
+            merge_keys: false,
         })
+    }
+
+    pub fn to_csv(&self, csv_path: &str) -> Result<(), MidiBeatError> {
+        let mut writer = csv::Writer::from_path(csv_path)?;
+        writer.write_record(["lane", "name", "key", "alt"])?;
+
+        let alt = |key: &u8| match self.alt_map.get(key) {
+            Some(groups) => groups.iter().map(|group| group.to_string()).join(" "),
+            None => String::new(),
+        };
+
+        for (lane, keys) in self.perc_map.iter().enumerate() {
+            for key in keys {
+                writer.write_record(&[lane.to_string(), self.lane_names[lane].clone(), key.to_string(), alt(key)])?;
+            }
+        }
+
+        // fallback only keys
+        let lane_keys: Vec<u8> = self.perc_map.iter().flatten().cloned().collect();
+        for key in self.alt_map.keys().filter(|key| !lane_keys.contains(key)).sorted() {
+            writer.write_record(&[String::new(), String::new(), key.to_string(), alt(key)])?;
+        }
+
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+pub const DEFAULT_LAYOUT: &str = "default";
+pub const LAYOUT_NAMES: [&str; 5] = ["default", "extended", "gmd9", "td11", "minimal3"];
+
+pub fn get_layout(name: &str) -> Option<Layout> {
+    let (lane_names, perc_map, alt_map): (Vec<&str>, Vec<Vec<u8>>, HashMap<u8, Vec<usize>>) = match name {
+        "default" => (
+            vec![
+                "kick", "snare", "low_tom", "mid_tom", "high_tom", "closed_hh", "open_hh_crash",
+                "ride",
+            ],
+            get_perc_map().to_vec(),
+            get_alt_reverse_perc_map(),
+        ),
+        "extended" => (
+            vec![
+                "kick", "snare", "low_tom", "mid_tom", "high_tom", "closed_hh", "open_hh_crash",
+                "ride", "hand_perc", "small_perc",
+            ],
+            get_extended_perc_map().to_vec(),
+            get_extended_alt_reverse_perc_map(),
+        ),
+        "gmd9" => (
+            vec![
+                "kick", "snare", "closed_hh", "open_hh", "low_tom", "mid_tom", "high_tom",
+                "crash", "ride",
+            ],
+            get_gmd_perc_map().to_vec(),
+            get_gmd_alt_reverse_perc_map(),
+        ),
+        "td11" => (
+            vec![
+                "kick", "snare_head", "snare_rim", "snare_x_stick", "tom_1", "tom_1_rim",
+                "tom_2", "tom_2_rim", "tom_3", "tom_3_rim", "hh_open_bow", "hh_open_edge",
+                "hh_closed_bow", "hh_closed_edge", "hh_pedal", "crash_1_bow", "crash_1_edge",
+                "crash_2_bow", "crash_2_edge", "ride_bow", "ride_edge", "ride_bell",
+            ],
+            get_td11_perc_map().to_vec(),
+            get_td11_alt_reverse_perc_map(),
+        ),
+        "minimal3" => (
+            vec!["kick", "snare", "hh"],
+            get_minimal_perc_map().to_vec(),
+            get_minimal_alt_reverse_perc_map(),
+        ),
+        _ => return None,
+    };
+
+    Some(Layout {
+        name: name.to_owned(),
+        lane_names: lane_names.iter().map(|lane_name| lane_name.to_string()).collect(),
+        perc_map,
+        alt_map,
+        // the GMD and TD-11 classes are pitch classes, every key of a class is the class
+        merge_keys: name == "gmd9" || name == "td11",
+    })
+}
+
+fn parse_field<T: std::str::FromStr>(field: Option<&str>, name: &str) -> Result<T, MidiBeatError> {
+    let field = field.unwrap_or("").trim();
+    field
+        .parse()
+        .map_err(|_| MidiBeatError::Config(format!("invalid {} '{}' in layout CSV", name, field)))
+}
+
+// preset name, or path to a layout CSV
+pub fn load_layout(name_or_path: &str) -> Result<Layout, MidiBeatError> {
+    match get_layout(name_or_path) {
+        Some(layout) => Ok(layout),
+        None if Path::new(name_or_path).is_file() => Layout::from_csv(name_or_path),
+        None => Err(MidiBeatError::Config(format!(
+            "unknown layout {}, available layouts: {} or a layout CSV path",
+            name_or_path,
+            LAYOUT_NAMES.join(", ")
+        ))),
+    }
+}
 
-        // @TODO switch to 96 ???
-        // filter tracks whose TS not compatible with bar RESOLUTION of 32
-        .filter(|(track, _)| track.get_bar_track_duration() % RESOLUTION == 0)
-        // filter TS only 4/4
-        // @TODO will need other TS
-        .filter(|(track, _)| {
-            return (track.time_signature.0 == 4 && track.time_signature.1 == 4)
-                || (track.time_signature.0 == 4 && track.time_signature.1 == 2)
-                || (track.time_signature.0 == 2 && track.time_signature.1 == 2);
+// reason why a track can't be turned into a grid, if any
+#[allow(clippy::absurd_extreme_comparisons)]
+pub fn get_track_rejection(
+    track: &DrumTrack,
+    track_perc_map: &Vec<Option<u8>>,
+    resolution: usize,
+) -> Option<&'static str> {
+    // filter tracks with less than 1 mapped percs
+    let percs_number = track_perc_map.iter().filter(|option_key| option_key.is_some()).count();
+
+    if percs_number <= THRESH_NON_EMPTY_TRACKS {
+        return Some("not enough mapped percs");
+    }
+
+    // @TODO switch to 96 ???
+    // filter tracks whose TS not compatible with bar resolution
+    let bar_tick_duration = track.get_bar_track_duration();
+    if bar_tick_duration == 0 || !bar_tick_duration.is_multiple_of(resolution) {
+        return Some("time signature not compatible with the bar resolution");
+    }
+
+    // filter TS only 4/4
+    // @TODO will need other TS
+    let supported_time_signature = matches!(
+        (track.time_signature.0, track.time_signature.1),
+        (4, 4) | (4, 2) | (2, 2)
+    );
+
+    if !supported_time_signature {
+        return Some("time signature not supported");
+    }
+
+    None
+}
+
+// tracks which can be turned into grids for a given layout and resolution, as the layout grids
+// them, with their index in the pool and their perc map
+pub fn get_mappable_tracks<'a>(
+    track_pool: &'a [DrumTrack],
+    layout: &Layout,
+    resolution: usize,
+) -> Vec<(usize, Cow<'a, DrumTrack>, Vec<Option<u8>>)> {
+    track_pool
+        .iter()
+        .enumerate()
+        .map(|(track_index, track)| {
+            let lane_track = layout.get_lane_track(track);
+            let track_perc_map = layout.get_track_perc_map(&lane_track);
+            (track_index, lane_track, track_perc_map)
         })
-        // map to a Vec of bars
-        .map(|(track, track_perc_map)| return track.to_grid(&track_perc_map))
-        // flatten everything into a vec of bars
-        .flatten()
-        .unique_by(|bar| {
-            let mut quantized_bar = [[0 as isize; NUMBER_OF_TRACKS]; RESOLUTION];
-            bar.iter().enumerate().for_each(|(step_index, step)| {
-                step.iter().enumerate().for_each(|(perc_index, event)| {
-                    // @TODO augment the quantization ??
-                    quantized_bar[step_index][perc_index] =
-                        (event[0] * 2.) as isize;
-                })
-            });
-            quantized_bar
+        .filter(|(_, track, track_perc_map)| get_track_rejection(track, track_perc_map, resolution).is_none())
+        .collect()
+}
+
+// grid of a mappable track of a pool
+#[cfg_attr(feature = "serialization", derive(Serialize, Deserialize))]
+pub struct TrackGrid {
+    // index in the pool
+    pub track_index: usize,
+    // key played by each lane in the track
+    pub track_perc_map: Vec<Option<u8>>,
+    pub bars: Vec<Bar>,
+}
+
+// grid of each mappable track of tracks at a resolution, or the reason it can't be built,
+// track indices are indices in tracks
+pub fn get_track_grids(
+    tracks: &[DrumTrack],
+    layout: &Layout,
+    resolution: usize,
+) -> Vec<Result<TrackGrid, (usize, MidiBeatError)>> {
+    get_mappable_tracks(tracks, layout, resolution)
+        .into_iter()
+        .map(
+            |(track_index, track, track_perc_map)| match track.to_grid_with_resolution(&track_perc_map, resolution) {
+                Ok(bars) => Ok(TrackGrid {
+                    track_index,
+                    track_perc_map,
+                    bars,
+                }),
+                Err(e) => Err((track_index, e)),
+            },
+        )
+        .collect()
+}
+
+// grids of the mappable tracks at a resolution, built once and shared by the rows, fill and
+// section scoring, tracks whose grid can't be built are skipped with a warning
+pub fn get_track_pool_grids(
+    track_pool: &Vec<DrumTrack>,
+    layout: &Layout,
+    resolution: usize,
+    diagnostics: &mut Diagnostics,
+) -> Vec<TrackGrid> {
+    get_track_grids(track_pool, layout, resolution)
+        .into_iter()
+        .filter_map(|track_grid| match track_grid {
+            Ok(track_grid) => Some(track_grid),
+            Err((track_index, e)) => {
+                diagnostics.warn("skipped_track", Some(track_index), e.to_string());
+                None
+            }
         })
-        .collect();
+        .collect()
+}
 
-    let number_of_bars = flattened_bars.len();
+pub fn process_track_pool(
+    track_pool: &Vec<DrumTrack>,
+    layout: &Layout,
+    diagnostics: &mut Diagnostics,
+) -> Result<Array<f32, Ix4>, MidiBeatError> {
+    let track_grids = get_track_pool_grids(track_pool, layout, RESOLUTION, diagnostics);
+    process_track_pool_with_sources(&track_grids, layout).map(|(bars, _)| bars)
+}
+
+// same as process_track_pool from the RESOLUTION grids of the pool,
+// also returns the track and bar each bar comes from
+pub fn process_track_pool_with_sources(
+    track_grids: &[TrackGrid],
+    layout: &Layout,
+) -> Result<(Array<f32, Ix4>, Vec<BarSource>), MidiBeatError> {
+    let number_of_lanes = layout.number_of_lanes();
+
+    // flatten everything into a vec of bars
+    let (flattened_bars, sources): (Vec<&Bar>, Vec<BarSource>) = track_grids
+        .iter()
+        .flat_map(|track_grid| {
+            let track_index = track_grid.track_index;
+            track_grid
+                .bars
+                .iter()
+                .enumerate()
+                .map(move |(bar_index, bar)| (bar, BarSource { track_index, bar_index }))
+        })
+        // @TODO augment the quantization ?? (see Bar equality)
+        .unique_by(|(bar, _)| bar.dedup_key())
+        .unzip();
 
     // special filtering operation
     // used to shape datasets better
     // like "select only four to floor for techno"
     // and other properties
 
-    let flattened_data: Vec<f32> = flattened_bars
-        .iter()
-        .flatten()
+    let bars = bars_to_array(&flattened_bars, RESOLUTION, number_of_lanes)?;
+    Ok((bars, sources))
+}
+
+// non silent windows of a track, windows never cross the end of the track
+fn get_track_windows(
+    track_index: usize,
+    bars: &[Bar],
+    window_bars: usize,
+    hop: usize,
+) -> Result<Vec<(Bar, BarSource)>, MidiBeatError> {
+    let mut windows: Vec<(Bar, BarSource)> = vec![];
+    for (bar_index, window) in bars.windows(window_bars).enumerate().step_by(hop) {
+        let views: Vec<ArrayView<f32, Ix3>> = window.iter().map(|bar| bar.view()).collect();
+        let window = Bar::from_array(concatenate(Axis(0), &views)?)?;
+
+        // drop silent windows
+        if !window.is_silent() {
+            windows.push((window, BarSource { track_index, bar_index }));
+        }
+    }
+    Ok(windows)
+}
+
+// windows of window_bars consecutive bars of a same track, starting every hop bars, from the grids
+// of the pool at resolution, shape (windows, window_bars * resolution, lanes, 2)
+// silent windows are dropped and dedup is applied to whole windows
+// also returns the track and first bar each window comes from
+pub fn process_track_pool_windows(
+    track_grids: &[TrackGrid],
+    layout: &Layout,
+    resolution: usize,
+    window_bars: usize,
+    hop: usize,
+    diagnostics: &mut Diagnostics,
+) -> Result<(Array<f32, Ix4>, Vec<BarSource>), MidiBeatError> {
+    if window_bars == 0 || hop == 0 {
+        return Err(MidiBeatError::Config(format!(
+            "windows of {} bars with a hop of {} bars",
+            window_bars, hop
+        )));
+    }
+
+    let number_of_lanes = layout.number_of_lanes();
+
+    let mut track_windows: Vec<Vec<(Bar, BarSource)>> = vec![];
+    for track_grid in track_grids.iter() {
+        match get_track_windows(track_grid.track_index, &track_grid.bars, window_bars, hop) {
+            Ok(windows) => track_windows.push(windows),
+            Err(e) => diagnostics.warn("skipped_track", Some(track_grid.track_index), e.to_string()),
+        }
+    }
+
+    let (windows, sources): (Vec<Bar>, Vec<BarSource>) = track_windows
+        .into_iter()
         .flatten()
+        .unique_by(|(window, _)| window.dedup_key())
+        .unzip();
+
+    let windows = bars_to_array(&windows, window_bars * resolution, number_of_lanes)?;
+    Ok((windows, sources))
+}
+
+// (context, target) pairs of a track whose target isn't silent
+fn get_track_pairs(
+    track_index: usize,
+    bars: &[Bar],
+    context_bars: usize,
+    distinct_target: bool,
+) -> Result<Vec<((Bar, Bar), BarSource)>, MidiBeatError> {
+    let mut pairs: Vec<((Bar, Bar), BarSource)> = vec![];
+    for (bar_index, window) in bars.windows(context_bars + 1).enumerate() {
+        let (context, target) = window.split_at(context_bars);
+        let target = &target[0];
+
+        if target.is_silent() || (distinct_target && context.contains(target)) {
+            continue;
+        }
+
+        let views: Vec<ArrayView<f32, Ix3>> = context.iter().map(|bar| bar.view()).collect();
+        let context = Bar::from_array(concatenate(Axis(0), &views)?)?;
+
+        pairs.push(((context, target.clone()), BarSource { track_index, bar_index }));
+    }
+    Ok(pairs)
+}
+
+// (previous context_bars bars, next bar) pairs of a same track, from the RESOLUTION grids of the pool,
+// shapes (pairs, context_bars * RESOLUTION, lanes, 2) and (pairs, RESOLUTION, lanes, 2)
+// pairs with a silent target are dropped and dedup is applied to whole pairs,
+// distinct_target only keeps the pairs whose target is none of the context bars
+// also returns the track and first context bar each pair comes from
+pub fn process_track_pool_pairs(
+    track_grids: &[TrackGrid],
+    layout: &Layout,
+    context_bars: usize,
+    distinct_target: bool,
+    diagnostics: &mut Diagnostics,
+) -> Result<(Array<f32, Ix4>, Array<f32, Ix4>, Vec<BarSource>), MidiBeatError> {
+    if context_bars == 0 {
+        return Err(MidiBeatError::Config("pairs need at least 1 context bar".to_owned()));
+    }
+
+    let number_of_lanes = layout.number_of_lanes();
+
+    let mut track_pairs: Vec<Vec<((Bar, Bar), BarSource)>> = vec![];
+    for track_grid in track_grids.iter() {
+        match get_track_pairs(track_grid.track_index, &track_grid.bars, context_bars, distinct_target) {
+            Ok(pairs) => track_pairs.push(pairs),
+            Err(e) => diagnostics.warn("skipped_track", Some(track_grid.track_index), e.to_string()),
+        }
+    }
+
+    let (pairs, sources): (Vec<(Bar, Bar)>, Vec<BarSource>) = track_pairs
+        .into_iter()
         .flatten()
-        .map(|val| *val)
-        .collect();
+        .unique_by(|((context, target), _)| (context.dedup_key(), target.dedup_key()))
+        .unzip();
+    let (contexts, targets): (Vec<Bar>, Vec<Bar>) = pairs.into_iter().unzip();
 
-    Array::from_shape_vec((number_of_bars, RESOLUTION, NUMBER_OF_TRACKS, 2), flattened_data)
+    let contexts = bars_to_array(&contexts, context_bars * RESOLUTION, number_of_lanes)?;
+    let targets = bars_to_array(&targets, RESOLUTION, number_of_lanes)?;
+    Ok((contexts, targets, sources))
 }
 
 // old terminal display
@@ -235,7 +919,8 @@ fn draw_array(array: Array<f32, Ix4>) {
 }
 
 
-// has kick every beat filter
-fn has_kick_every_beat(patt: [[[f32; 2]; NUMBER_OF_TRACKS]; RESOLUTION]) -> bool {
-    false
+// has kick every beat filter, kick being the first lane and the bar 4 beats long
+pub fn has_kick_every_beat(bar: &Bar) -> bool {
+    let beat_len = (bar.number_of_steps() / 4).max(1);
+    (0..bar.number_of_steps()).step_by(beat_len).all(|step| bar.hit(step, 0))
 }
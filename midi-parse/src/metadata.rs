@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::{Component, Path, PathBuf};
+
+use itertools::Itertools;
+use ndarray::{Array, Ix1};
+use regex::Regex;
+
+use crate::error::MidiBeatError;
+
+// rows of a dataset info CSV, indexed by the MIDI path column
+pub struct DatasetMetadata {
+    pub path_column: String,
+    pub columns: Vec<String>,
+    rows: Vec<(String, BTreeMap<String, String>)>,
+    // file name -> row indexes, to avoid scanning every row for each file
+    by_file_name: HashMap<String, Vec<usize>>,
+}
+
+impl DatasetMetadata {
+    pub fn from_csv(csv_path: &str, path_column: &str) -> Result<DatasetMetadata, MidiBeatError> {
+        let mut reader = csv::Reader::from_path(csv_path)?;
+        let headers: Vec<String> = reader.headers()?.iter().map(|h| h.to_owned()).collect();
+
+        let path_index = match headers.iter().position(|h| h == path_column) {
+            Some(index) => index,
+            None => {
+                return Err(MidiBeatError::Config(format!(
+                    "column '{}' not found in {}",
+                    path_column, csv_path
+                )))
+            }
+        };
+
+        let columns: Vec<String> = headers
+            .iter()
+            .filter(|h| *h != path_column)
+            .cloned()
+            .collect();
+
+        let mut rows: Vec<(String, BTreeMap<String, String>)> = vec![];
+        let mut by_file_name: HashMap<String, Vec<usize>> = HashMap::new();
+
+        for record in reader.records() {
+            let record = record?;
+            let midi_path = record.get(path_index).unwrap_or("").replace('\\', "/");
+
+            let values: BTreeMap<String, String> = headers
+                .iter()
+                .zip(record.iter())
+                .filter(|(h, _)| *h != path_column)
+                .map(|(h, value)| (h.clone(), value.to_owned()))
+                .collect();
+
+            if let Some(file_name) = Path::new(&midi_path).file_name() {
+                by_file_name
+                    .entry(file_name.to_string_lossy().to_string())
+                    .or_default()
+                    .push(rows.len());
+            }
+
+            rows.push((midi_path, values));
+        }
+
+        Ok(DatasetMetadata {
+            path_column: path_column.to_owned(),
+            columns,
+            rows,
+            by_file_name,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.rows.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    // row whose MIDI path is a suffix of the given file path
+    pub fn get(&self, file_path: &Path) -> Option<&BTreeMap<String, String>> {
+        let file_name = file_path.file_name()?.to_string_lossy().to_string();
+
+        self.by_file_name
+            .get(&file_name)?
+            .iter()
+            .map(|&index| &self.rows[index])
+            .find(|(midi_path, _)| file_path.ends_with(midi_path))
+            .map(|(_, values)| values)
+    }
+}
+
+pub enum LabelArray {
+    // every present value parses as a number, missing values are NaN
+    Numeric(Array<f32, Ix1>),
+    // index in the vocabulary, missing values are -1
+    Categorical(Array<i64, Ix1>, Vec<String>),
+}
+
+pub fn encode_labels(values: &Vec<Option<&String>>) -> LabelArray {
+    let present: Vec<&String> = values
+        .iter()
+        .filter_map(|value| *value)
+        .filter(|value| !value.is_empty())
+        .collect();
+
+    if !present.is_empty() && present.iter().all(|value| value.parse::<f32>().is_ok()) {
+        let numeric: Vec<f32> = values
+            .iter()
+            .map(|value| match value {
+                Some(value) => value.parse::<f32>().unwrap_or(f32::NAN),
+                None => f32::NAN,
+            })
+            .collect();
+
+        return LabelArray::Numeric(Array::from(numeric));
+    }
+
+    let (codes, vocabulary) = encode_categorical_labels(values);
+    LabelArray::Categorical(codes, vocabulary)
+}
+
+// index in the sorted vocabulary of present values, missing values are -1
+pub fn encode_categorical_labels(values: &Vec<Option<&String>>) -> (Array<i64, Ix1>, Vec<String>) {
+    let vocabulary: Vec<String> = values
+        .iter()
+        .filter_map(|value| *value)
+        .filter(|value| !value.is_empty())
+        .cloned()
+        .unique()
+        .sorted()
+        .collect();
+    let codes_by_label: HashMap<&String, i64> = vocabulary
+        .iter()
+        .enumerate()
+        .map(|(code, label)| (label, code as i64))
+        .collect();
+
+    let codes: Vec<i64> = values
+        .iter()
+        .map(|value| match value {
+            Some(value) => *codes_by_label.get(value).unwrap_or(&-1),
+            None => -1,
+        })
+        .collect();
+
+    (Array::from(codes), vocabulary)
+}
+
+// newline separated vocabulary as utf-8 bytes, so it can be stored next to the arrays
+pub fn vocabulary_to_bytes(vocabulary: &[String]) -> Array<u8, Ix1> {
+    Array::from(vocabulary.join("\n").into_bytes())
+}
+
+enum PathLabelRule {
+    // component index from the glob root, negative from the end (-1 is the file name)
+    Component(isize),
+    // first capture group, or whole match, on the path with '/' separators
+    Regex(Regex),
+}
+
+// label derived from the path of each file, like the genre folder of a corpus
+pub struct PathLabel {
+    pub name: String,
+    rule: PathLabelRule,
+}
+
+impl PathLabel {
+    // "name=index" or "name=re:regex"
+    pub fn parse(spec: &str) -> Result<PathLabel, MidiBeatError> {
+        let (name, rule) = match spec.find('=') {
+            Some(index) => (&spec[..index], &spec[index + 1..]),
+            None => {
+                return Err(MidiBeatError::Config(format!(
+                    "path label '{}' should be name=index or name=re:regex",
+                    spec
+                )))
+            }
+        };
+
+        let rule = if let Some(pattern) = rule.strip_prefix("re:") {
+            match Regex::new(pattern) {
+                Ok(regex) => PathLabelRule::Regex(regex),
+                Err(e) => return Err(MidiBeatError::Config(format!("path label '{}': {}", name, e))),
+            }
+        } else {
+            match rule.parse::<isize>() {
+                Ok(index) => PathLabelRule::Component(index),
+                Err(_) => {
+                    return Err(MidiBeatError::Config(format!(
+                        "path label '{}': '{}' is neither a component index nor re:regex",
+                        name, rule
+                    )))
+                }
+            }
+        };
+
+        Ok(PathLabel {
+            name: name.to_owned(),
+            rule,
+        })
+    }
+
+    pub fn extract(&self, path: &Path, glob_root: &Path) -> Option<String> {
+        match &self.rule {
+            PathLabelRule::Component(index) => {
+                let relative = path.strip_prefix(glob_root).unwrap_or(path);
+                let components: Vec<String> = relative
+                    .components()
+                    .filter_map(|component| match component {
+                        Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                        _ => None,
+                    })
+                    .collect();
+
+                let position = if *index < 0 {
+                    components.len() as isize + index
+                } else {
+                    *index
+                };
+                if position < 0 {
+                    return None;
+                }
+                components.get(position as usize).cloned()
+            }
+            PathLabelRule::Regex(regex) => {
+                let path = path.to_string_lossy().replace('\\', "/");
+                let captures = regex.captures(&path)?;
+                captures
+                    .get(1)
+                    .or_else(|| captures.get(0))
+                    .map(|label| label.as_str().to_owned())
+            }
+        }
+    }
+}
+
+// literal directories of a glob pattern, before the first wildcard
+pub fn get_glob_root(pattern: &str) -> PathBuf {
+    Path::new(pattern)
+        .components()
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '['])
+        })
+        .collect()
+}
@@ -1,25 +1,37 @@
 use midly::Smf;
 use itertools::Itertools;
+use std::fs;
+use std::path::Path;
 
 use crate::datatypes::DrumTrack;
+use crate::error::MidiBeatError;
 
 use crate::utils::{
   track_has_beat_event,
   filter_beat_events,
   get_unique_time_signature,
+  get_tempo,
+  get_markers,
+  get_time_stretch,
   key_footprints_intersect,
-  // to_smf
 };
 
-pub fn filter_beat(smf: Smf, drum_channel: bool) -> Vec<DrumTrack> {
-  let mut ppqn: u16 = 0;
+// read and parse a MIDI file, then filter its beat tracks
+pub fn parse_file(path: &Path, drum_channel: bool) -> Result<Vec<DrumTrack>, MidiBeatError> {
+  let data = fs::read(path)?;
+  parse_bytes(&data, drum_channel)
+}
 
-  match smf.header.timing {
-    midly::Timing::Metrical(tpb) => {
-      ppqn = tpb.as_int();
-    }
-    midly::Timing::Timecode(_, _) => {}
-  }
+pub fn parse_bytes(data: &[u8], drum_channel: bool) -> Result<Vec<DrumTrack>, MidiBeatError> {
+  let smf = Smf::parse(data)?;
+  filter_beat(smf, drum_channel)
+}
+
+pub fn filter_beat(smf: Smf, drum_channel: bool) -> Result<Vec<DrumTrack>, MidiBeatError> {
+  let ppqn: u16 = match smf.header.timing {
+    midly::Timing::Metrical(tpb) => tpb.as_int(),
+    midly::Timing::Timecode(_, _) => return Err(MidiBeatError::TimecodeTiming),
+  };
 
   // println!("number of tracks {:?}", smf.tracks.iter().len());
 
@@ -36,7 +48,7 @@ pub fn filter_beat(smf: Smf, drum_channel: bool) -> Vec<DrumTrack> {
   // println!("unique_time_signature {:?}", unique_time_signature);
 
   // call merge_same_signature_tracks with tracks featuring same time_signature
-  let merged_drum_tracks: Vec<DrumTrack> = unique_time_signature
+  let merged_drum_tracks: Vec<Vec<DrumTrack>> = unique_time_signature
     .iter()
     .map(|time_signature| {
       let same_signature_tracks: Vec<DrumTrack> = tracks
@@ -47,17 +59,29 @@ pub fn filter_beat(smf: Smf, drum_channel: bool) -> Vec<DrumTrack> {
         
       merge_same_signature_tracks(same_signature_tracks, *time_signature, ppqn)  
     })
-    .flatten()
-    .collect();
+    .collect::<Result<Vec<Vec<DrumTrack>>, MidiBeatError>>()?;
 
-  // re write smf files just for debug  
-  // to_smf(smf, &merged_drum_tracks, path);
+  let tempo = get_tempo(&smf.tracks);
+  let markers = get_markers(&smf.tracks);
 
-  merged_drum_tracks
+  Ok(merged_drum_tracks
+    .into_iter()
+    .flatten()
+    .map(|mut track| {
+      track.tempo = tempo;
+      // same time scaling as the track events
+      let stretch = get_time_stretch(track.time_signature);
+      track.markers = markers
+        .iter()
+        .map(|(tick, text)| (tick * stretch, text.clone()))
+        .collect();
+      track
+    })
+    .collect())
 }
 
 
-fn merge_same_signature_tracks(mut tracks: Vec<DrumTrack>, time_signature: (u8, u8, u8, u8), ppqn: u16) -> Vec<DrumTrack> {
+fn merge_same_signature_tracks(mut tracks: Vec<DrumTrack>, time_signature: (u8, u8, u8, u8), ppqn: u16) -> Result<Vec<DrumTrack>, MidiBeatError> {
   let mut mergeable_tracks: Vec<DrumTrack> = vec![];
   let mut resulting_tracks: Vec<DrumTrack> = vec![];
 
@@ -65,7 +89,8 @@ fn merge_same_signature_tracks(mut tracks: Vec<DrumTrack>, time_signature: (u8,
   let index_of_widest_key_footprint: usize = tracks
     .iter()
     .map(|track| track.get_key_footprint())
-    .position_max_by(|x, y| x.len().cmp(&y.len())).unwrap();
+    .position_max_by(|x, y| x.len().cmp(&y.len()))
+    .ok_or(MidiBeatError::NoTracks)?;
 
   // get associated DrumTrack 
   let base_track = &tracks[index_of_widest_key_footprint].clone();
@@ -100,5 +125,5 @@ fn merge_same_signature_tracks(mut tracks: Vec<DrumTrack>, time_signature: (u8,
   
   resulting_tracks.push(merged_track);
 
-  resulting_tracks
+  Ok(resulting_tracks)
 }
@@ -0,0 +1,118 @@
+use ndarray::{Array, Ix1, Ix2};
+use std::collections::HashMap;
+
+#[cfg(feature = "serialization")]
+use serde::Serialize;
+
+use crate::datatypes::DrumTrack;
+#[cfg(feature = "serialization")]
+use crate::error::MidiBeatError;
+use crate::map::Layout;
+
+// track of the pool and bar of that track a row of the dataset comes from
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct BarSource {
+    pub track_index: usize,
+    // first bar for multi bar windows
+    pub bar_index: usize,
+}
+
+// file of a track of the pool, and index of the track among the tracks parsed from it
+#[derive(Clone, Debug)]
+pub struct TrackOrigin {
+    pub path: String,
+    pub track_index: usize,
+}
+
+// everything needed to trace a row of the dataset back to its file
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serialization", derive(Serialize))]
+pub struct Provenance {
+    pub path: String,
+    pub track_index: usize,
+    pub bar_index: usize,
+    pub time_signature: (u8, u8, u8, u8),
+    pub ppqn: u16,
+    pub tempo: u32,
+    pub layout: String,
+}
+
+// origins are parallel to the track pool, sources to the dataset rows
+pub fn get_provenance(
+    track_pool: &Vec<DrumTrack>,
+    origins: &Vec<TrackOrigin>,
+    sources: &[BarSource],
+    layout: &Layout,
+) -> Vec<Provenance> {
+    sources
+        .iter()
+        .map(|source| {
+            let track = &track_pool[source.track_index];
+            let origin = &origins[source.track_index];
+
+            Provenance {
+                path: origin.path.clone(),
+                track_index: origin.track_index,
+                bar_index: source.bar_index,
+                time_signature: track.time_signature,
+                ppqn: track.ppqn,
+                tempo: track.tempo,
+                layout: layout.name.clone(),
+            }
+        })
+        .collect()
+}
+
+// one JSON object per line, one line per row
+#[cfg(feature = "serialization")]
+pub fn provenance_to_jsonl(provenance: &[Provenance]) -> Result<String, MidiBeatError> {
+    provenance
+        .iter()
+        .map(|row| crate::serialization::to_json(row).map(|json| json + "\n"))
+        .collect()
+}
+
+// provenance as arrays aligned with the dataset rows, paths are indexes in the paths vocabulary
+pub struct ProvenanceArrays {
+    pub path: Array<i64, Ix1>,
+    pub paths: Vec<String>,
+    pub track: Array<i64, Ix1>,
+    pub bar: Array<i64, Ix1>,
+    // (rows, 4), raw MIDI time signature
+    pub time_signature: Array<u8, Ix2>,
+    pub ppqn: Array<i64, Ix1>,
+    pub tempo: Array<i64, Ix1>,
+}
+
+pub fn to_provenance_arrays(provenance: &[Provenance]) -> ProvenanceArrays {
+    let mut paths: Vec<String> = vec![];
+    let mut codes_by_path: HashMap<&String, i64> = HashMap::new();
+
+    let path_codes: Vec<i64> = provenance
+        .iter()
+        .map(|row| {
+            *codes_by_path.entry(&row.path).or_insert_with(|| {
+                paths.push(row.path.clone());
+                paths.len() as i64 - 1
+            })
+        })
+        .collect();
+
+    let time_signature: Vec<u8> = provenance
+        .iter()
+        .flat_map(|row| {
+            let ts = row.time_signature;
+            vec![ts.0, ts.1, ts.2, ts.3]
+        })
+        .collect();
+
+    ProvenanceArrays {
+        path: Array::from(path_codes),
+        paths,
+        track: provenance.iter().map(|row| row.track_index as i64).collect(),
+        bar: provenance.iter().map(|row| row.bar_index as i64).collect(),
+        time_signature: Array::from_shape_vec((provenance.len(), 4), time_signature).unwrap(),
+        ppqn: provenance.iter().map(|row| row.ppqn as i64).collect(),
+        tempo: provenance.iter().map(|row| row.tempo as i64).collect(),
+    }
+}
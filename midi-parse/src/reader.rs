@@ -0,0 +1,145 @@
+use std::fs::{self, File};
+use std::path::{Path, PathBuf};
+
+use ndarray::{stack, ArrayD, ArrayView, Axis, Ix3, Ix4, IxDyn};
+use ndarray_npy::{read_npy, NpzReader};
+use serde::Deserialize;
+
+use crate::datatypes::Bar;
+use crate::error::MidiBeatError;
+use crate::manifest::{Manifest, ManifestShard, MANIFEST_VERSION};
+use crate::serialization::read_json;
+
+// reads the grids of a dataset through its manifest, one shard at a time
+pub struct ManifestReader {
+    pub manifest: Manifest,
+    directory: PathBuf,
+}
+
+fn npy_error(e: impl std::error::Error) -> MidiBeatError {
+    MidiBeatError::Serialization(e.to_string())
+}
+
+// F32 tensor of a safetensors file
+fn read_safetensors_array(path: &Path, name: &str) -> Result<ArrayD<f32>, MidiBeatError> {
+    let bytes = fs::read(path)?;
+    let error = |message: &str| MidiBeatError::Serialization(format!("{}: {}", path.display(), message));
+
+    let header_length = bytes
+        .get(..8)
+        .map(|length| u64::from_le_bytes([length[0], length[1], length[2], length[3], length[4], length[5], length[6], length[7]]) as usize)
+        .ok_or_else(|| error("missing header"))?;
+    let header: serde_json::Value = bytes
+        .get(8..8 + header_length)
+        .and_then(|header| serde_json::from_slice(header).ok())
+        .ok_or_else(|| error("invalid header"))?;
+    let tensor: SafetensorsTensor = header
+        .get(name)
+        .and_then(|tensor| serde_json::from_value(tensor.clone()).ok())
+        .ok_or_else(|| error(&format!("no tensor {}", name)))?;
+    if tensor.dtype != "F32" {
+        return Err(error(&format!("{} is {}, not F32", name, tensor.dtype)));
+    }
+
+    let data = bytes
+        .get(8 + header_length + tensor.data_offsets[0]..8 + header_length + tensor.data_offsets[1])
+        .ok_or_else(|| error("truncated data"))?;
+    let values: Vec<f32> = data
+        .chunks_exact(4)
+        .map(|value| f32::from_le_bytes([value[0], value[1], value[2], value[3]]))
+        .collect();
+    Ok(ArrayD::from_shape_vec(IxDyn(&tensor.shape), values)?)
+}
+
+#[derive(Deserialize)]
+struct SafetensorsTensor {
+    dtype: String,
+    shape: Vec<usize>,
+    data_offsets: [usize; 2],
+}
+
+impl ManifestReader {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<ManifestReader, MidiBeatError> {
+        let manifest: Manifest = read_json(&path)?;
+        if manifest.version > MANIFEST_VERSION {
+            return Err(MidiBeatError::Serialization(format!(
+                "manifest version {} is newer than {}",
+                manifest.version, MANIFEST_VERSION
+            )));
+        }
+
+        if !["npz", "npy", "safetensors"].contains(&manifest.format.as_str()) {
+            return Err(MidiBeatError::Config(format!("{} shards can't be read back", manifest.format)));
+        }
+
+        let directory = path.as_ref().parent().map(|parent| parent.to_path_buf()).unwrap_or_default();
+        Ok(ManifestReader { manifest, directory })
+    }
+
+    pub fn shard_path(&self, shard: &ManifestShard) -> PathBuf {
+        self.directory.join(&shard.path)
+    }
+
+    // F32 array of a shard
+    pub fn read_array(&self, shard: &ManifestShard, name: &str) -> Result<ArrayD<f32>, MidiBeatError> {
+        let path = self.shard_path(shard);
+        match self.manifest.format.as_str() {
+            "npz" => NpzReader::new(File::open(&path)?)
+                .map_err(npy_error)?
+                .by_name(name)
+                .map_err(npy_error),
+            "npy" => read_npy(path.join(format!("{}.npy", name))).map_err(npy_error),
+            "safetensors" => read_safetensors_array(&path, name),
+            format => Err(MidiBeatError::Config(format!("{} shards can't be read back", format))),
+        }
+    }
+
+    // bars of each row of a shard, context bars then the target bar for pairs
+    pub fn read_rows(&self, shard: &ManifestShard) -> Result<Vec<Vec<Bar>>, MidiBeatError> {
+        let has_array = |name: &str| shard.arrays.iter().any(|array| array.name == name);
+        let resolution = self.manifest.resolution;
+
+        let split_bars = |row: ArrayView<'_, f32, Ix3>| -> Result<Vec<Bar>, MidiBeatError> {
+            row.axis_chunks_iter(Axis(0), resolution).map(Bar::from_view).collect()
+        };
+
+        if has_array("x") {
+            let grids = self.read_array(shard, "x")?.into_dimensionality::<Ix4>()?;
+            grids.outer_iter().map(split_bars).collect()
+        } else if has_array("context") {
+            let contexts = self.read_array(shard, "context")?.into_dimensionality::<Ix4>()?;
+            let targets = self.read_array(shard, "target")?.into_dimensionality::<Ix4>()?;
+            contexts
+                .outer_iter()
+                .zip(targets.outer_iter())
+                .map(|(context, target)| {
+                    let mut bars = split_bars(context)?;
+                    bars.push(Bar::from_view(target)?);
+                    Ok(bars)
+                })
+                .collect()
+        } else {
+            // GMD tensors, (rows, steps, lanes) each
+            let velocities = self.read_array(shard, "velocities")?.into_dimensionality::<Ix3>()?;
+            let offsets = self.read_array(shard, "offsets")?.into_dimensionality::<Ix3>()?;
+            let grids = stack(Axis(3), &[velocities.view(), offsets.view()])?;
+            grids.outer_iter().map(split_bars).collect()
+        }
+    }
+
+    // rows of every shard in order
+    pub fn rows(&self) -> impl Iterator<Item = Result<Vec<Bar>, MidiBeatError>> + '_ {
+        self.manifest.shards.iter().flat_map(move |shard| match self.read_rows(shard) {
+            Ok(rows) => rows.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        })
+    }
+
+    // bars of every row of every shard in order
+    pub fn bars(&self) -> impl Iterator<Item = Result<Bar, MidiBeatError>> + '_ {
+        self.rows().flat_map(|row| match row {
+            Ok(bars) => bars.into_iter().map(Ok).collect::<Vec<_>>(),
+            Err(e) => vec![Err(e)],
+        })
+    }
+}
@@ -0,0 +1,185 @@
+use std::collections::HashMap;
+
+use ndarray::{Array, Ix2};
+
+use crate::datatypes::{Bar, DrumTrack};
+use crate::map::TrackGrid;
+use crate::provenance::BarSource;
+
+// bars on each side of a candidate boundary compared by the novelty curve
+const NOVELTY_BARS: usize = 4;
+// novelty needed for a boundary
+const NOVELTY_THRESHOLD: f32 = 0.25;
+const MIN_SECTION_BARS: usize = 2;
+// mean similarity above which two sections are the same kind (verse / chorus like)
+const SAME_KIND_SIMILARITY: f32 = 0.6;
+
+// contiguous bars of a track
+#[derive(Clone, Debug)]
+pub struct Section {
+    pub start_bar: usize,
+    pub length: usize,
+    // sections of a same kind have similar bars, kinds are numbered in order of appearance
+    pub kind: usize,
+    // marker starting the section, if any
+    pub marker: Option<String>,
+}
+
+// section information of one bar
+#[derive(Clone, Debug)]
+pub struct BarSection {
+    // index of the section in its track
+    pub section: usize,
+    pub kind: usize,
+    // index of the bar in its section
+    pub position: usize,
+    pub length: usize,
+    pub marker: Option<String>,
+}
+
+// hit similarity of every pair of bars
+pub fn get_self_similarity(bars: &[Bar]) -> Array<f32, Ix2> {
+    Array::from_shape_fn((bars.len(), bars.len()), |(i, j)| bars[i].hit_similarity(&bars[j]))
+}
+
+fn mean_block(similarity: &Array<f32, Ix2>, rows: (usize, usize), columns: (usize, usize)) -> f32 {
+    let count = (rows.1 - rows.0) * (columns.1 - columns.0);
+    if count == 0 {
+        return 0.;
+    }
+
+    let sum: f32 = (rows.0..rows.1)
+        .flat_map(|i| (columns.0..columns.1).map(move |j| (i, j)))
+        .map(|(i, j)| similarity[[i, j]])
+        .sum();
+    sum / count as f32
+}
+
+// checkerboard novelty: bars before and after the boundary are similar among themselves, not with each other
+fn get_novelty(similarity: &Array<f32, Ix2>, boundary: usize) -> f32 {
+    let number_of_bars = similarity.shape()[0];
+    let before = (boundary.saturating_sub(NOVELTY_BARS), boundary);
+    let after = (boundary, (boundary + NOVELTY_BARS).min(number_of_bars));
+
+    let within = (mean_block(similarity, before, before) + mean_block(similarity, after, after)) / 2.;
+    within - mean_block(similarity, before, after)
+}
+
+// sections from novelty peaks of the self similarity matrix, markers always start a section,
+// markers are (bar index, text)
+pub fn segment_bars(bars: &[Bar], markers: &[(usize, String)]) -> Vec<Section> {
+    if bars.is_empty() {
+        return vec![];
+    }
+
+    let similarity = get_self_similarity(bars);
+    let novelty: Vec<f32> = (0..bars.len()).map(|boundary| get_novelty(&similarity, boundary)).collect();
+
+    let mut boundaries: Vec<usize> = vec![0];
+    for boundary in 1..bars.len() {
+        let is_marker = markers.iter().any(|(bar_index, _)| *bar_index == boundary);
+        let is_peak = novelty[boundary] >= NOVELTY_THRESHOLD
+            && novelty[boundary] >= novelty[boundary - 1]
+            && novelty.get(boundary + 1).map(|next| novelty[boundary] >= *next).unwrap_or(true);
+        let far_enough = boundary - boundaries.last().unwrap() >= MIN_SECTION_BARS;
+
+        if is_marker || (is_peak && far_enough) {
+            boundaries.push(boundary);
+        }
+    }
+    boundaries.push(bars.len());
+
+    let mut sections: Vec<Section> = vec![];
+    for (start_bar, end_bar) in boundaries.iter().zip(boundaries.iter().skip(1)) {
+        let (start_bar, end_bar) = (*start_bar, *end_bar);
+
+        // kind of the most similar previous section, or a new kind
+        let kind = sections
+            .iter()
+            .map(|section| {
+                let similarity = mean_block(
+                    &similarity,
+                    (start_bar, end_bar),
+                    (section.start_bar, section.start_bar + section.length),
+                );
+                (section.kind, similarity)
+            })
+            .filter(|(_, similarity)| *similarity >= SAME_KIND_SIMILARITY)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap())
+            .map(|(kind, _)| kind)
+            .unwrap_or_else(|| sections.iter().map(|section| section.kind + 1).max().unwrap_or(0));
+
+        let marker = markers
+            .iter()
+            .filter(|(bar_index, _)| *bar_index == start_bar)
+            .map(|(_, text)| text.clone())
+            .next_back();
+
+        sections.push(Section {
+            start_bar,
+            length: end_bar - start_bar,
+            kind,
+            marker,
+        });
+    }
+
+    sections
+}
+
+pub fn get_bar_sections(sections: &[Section]) -> Vec<BarSection> {
+    sections
+        .iter()
+        .enumerate()
+        .flat_map(|(section_index, section)| {
+            (0..section.length).map(move |position| BarSection {
+                section: section_index,
+                kind: section.kind,
+                position,
+                length: section.length,
+                marker: section.marker.clone(),
+            })
+        })
+        .collect()
+}
+
+// sections of a track, markers are converted to bar indexes
+pub fn get_track_sections(track: &DrumTrack, bars: &[Bar]) -> Vec<Section> {
+    let bar_tick_duration = track.get_bar_track_duration().max(1);
+    let markers: Vec<(usize, String)> = track
+        .markers
+        .iter()
+        .map(|(tick, text)| (*tick as usize / bar_tick_duration, text.clone()))
+        .collect();
+
+    segment_bars(bars, &markers)
+}
+
+// section of every bar of every grid of the pool, by index in track_pool
+pub fn get_track_pool_sections(
+    track_pool: &Vec<DrumTrack>,
+    track_grids: &[TrackGrid],
+) -> HashMap<usize, Vec<BarSection>> {
+    track_grids
+        .iter()
+        .map(|track_grid| {
+            let sections = get_track_sections(&track_pool[track_grid.track_index], &track_grid.bars);
+            (track_grid.track_index, get_bar_sections(&sections))
+        })
+        .collect()
+}
+
+// section of the bar offset bars after the row source bar
+pub fn get_row_sections<'a>(
+    track_sections: &'a HashMap<usize, Vec<BarSection>>,
+    sources: &[BarSource],
+    offset: usize,
+) -> Vec<Option<&'a BarSection>> {
+    sources
+        .iter()
+        .map(|source| {
+            track_sections
+                .get(&source.track_index)
+                .and_then(|sections| sections.get(source.bar_index + offset))
+        })
+        .collect()
+}
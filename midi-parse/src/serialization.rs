@@ -0,0 +1,47 @@
+use std::fs;
+use std::path::Path;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::MidiBeatError;
+
+// tracks, layouts and bars can be exchanged as JSON (readable)
+// or as bincode (compact, for caches)
+
+pub fn to_json<T: Serialize>(value: &T) -> Result<String, MidiBeatError> {
+    serde_json::to_string(value).map_err(|e| MidiBeatError::Serialization(e.to_string()))
+}
+
+// indented, for files people read like dataset manifests
+pub fn to_json_pretty<T: Serialize>(value: &T) -> Result<String, MidiBeatError> {
+    serde_json::to_string_pretty(value).map_err(|e| MidiBeatError::Serialization(e.to_string()))
+}
+
+pub fn from_json<T: DeserializeOwned>(json: &str) -> Result<T, MidiBeatError> {
+    serde_json::from_str(json).map_err(|e| MidiBeatError::Serialization(e.to_string()))
+}
+
+pub fn to_binary<T: Serialize>(value: &T) -> Result<Vec<u8>, MidiBeatError> {
+    bincode::serialize(value).map_err(|e| MidiBeatError::Serialization(e.to_string()))
+}
+
+pub fn from_binary<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, MidiBeatError> {
+    bincode::deserialize(bytes).map_err(|e| MidiBeatError::Serialization(e.to_string()))
+}
+
+pub fn write_json<T: Serialize, P: AsRef<Path>>(path: P, value: &T) -> Result<(), MidiBeatError> {
+    Ok(fs::write(path, to_json(value)?)?)
+}
+
+pub fn read_json<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<T, MidiBeatError> {
+    from_json(&fs::read_to_string(path)?)
+}
+
+pub fn write_binary<T: Serialize, P: AsRef<Path>>(path: P, value: &T) -> Result<(), MidiBeatError> {
+    Ok(fs::write(path, to_binary(value)?)?)
+}
+
+pub fn read_binary<T: DeserializeOwned, P: AsRef<Path>>(path: P) -> Result<T, MidiBeatError> {
+    from_binary(&fs::read(path)?)
+}
@@ -0,0 +1,117 @@
+use std::cmp::Reverse;
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::error::MidiBeatError;
+
+pub const SPLIT_NAMES: [&str; 3] = ["train", "val", "test"];
+// realized shares further than this from the asked ratios are reported
+pub const SPLIT_TOLERANCE: f32 = 0.05;
+
+// share of the rows going to train, val and test
+#[derive(Copy, Clone, Debug)]
+pub struct SplitRatios {
+    pub train: f32,
+    pub val: f32,
+    pub test: f32,
+}
+
+impl SplitRatios {
+    // "train,val,test", like "0.8,0.1,0.1", normalized to sum to 1
+    pub fn parse(spec: &str) -> Result<SplitRatios, MidiBeatError> {
+        let ratios: Vec<f32> = spec
+            .split(',')
+            .map(|ratio| ratio.trim().parse::<f32>())
+            .collect::<Result<Vec<f32>, _>>()
+            .map_err(|e| MidiBeatError::Config(format!("split ratios '{}': {}", spec, e)))?;
+
+        let total: f32 = ratios.iter().sum();
+        if ratios.len() != 3 || ratios.iter().any(|ratio| *ratio < 0.) || total <= 0. {
+            return Err(MidiBeatError::Config(format!(
+                "split ratios '{}' should be 3 positive numbers",
+                spec
+            )));
+        }
+
+        Ok(SplitRatios {
+            train: ratios[0] / total,
+            val: ratios[1] / total,
+            test: ratios[2] / total,
+        })
+    }
+}
+
+// FNV-1a, stable across platforms and Rust versions unlike the std hasher
+fn hash_group(group: &str, seed: u64) -> u64 {
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325 ^ seed;
+    for byte in group.bytes() {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+
+    // splitmix64 finalizer, so that close groups don't sort close
+    hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    hash ^ (hash >> 31)
+}
+
+// split index (0 train, 1 val, 2 test) of each row, rows of a same group always share a split.
+// Each group goes to the split whose cumulative ratio its seeded hash falls under, so a group keeps
+// its split when files are added or removed, and shares only match the ratios over many groups
+pub fn assign_splits(groups: &[String], ratios: &SplitRatios, seed: u64) -> Vec<u8> {
+    let shares = [ratios.train, ratios.val, ratios.test];
+    // rounding can leave the cumulative ratios just under 1, the last split with a share takes the rest
+    let last_split = (0..3).rev().find(|&split| shares[split] > 0.).unwrap_or(0);
+
+    groups
+        .iter()
+        .map(|group| {
+            // top 53 bits, uniform in [0, 1)
+            let position = (hash_group(group, seed) >> 11) as f64 / (1u64 << 53) as f64;
+            let mut threshold = 0.;
+            (0..3)
+                .find(|&split| {
+                    threshold += shares[split] as f64;
+                    shares[split] > 0. && position < threshold
+                })
+                .unwrap_or(last_split) as u8
+        })
+        .collect()
+}
+
+// same as assign_splits, with shares close to the ratios on few groups: groups go from the largest
+// to the smallest (by power of two of their rows, shuffled by seed within a size) to the split missing
+// the most rows to reach its share, so that small groups fill the gaps and splits with a share only
+// stay empty when there are fewer groups than splits. The split of a group depends on every other
+// group, adding or removing files can move groups between splits
+pub fn assign_splits_balanced(groups: &[String], ratios: &SplitRatios, seed: u64) -> Vec<u8> {
+    let mut rows_by_group: HashMap<&String, usize> = HashMap::new();
+    groups.iter().for_each(|group| *rows_by_group.entry(group).or_insert(0) += 1);
+
+    let total = groups.len() as f32;
+    let targets = [ratios.train * total, ratios.val * total, ratios.test * total];
+
+    let mut assigned_rows = [0usize; 3];
+    let split_by_group: HashMap<&String, u8> = rows_by_group
+        .into_iter()
+        .sorted_by_key(|(group, rows)| {
+            let size = usize::BITS - rows.leading_zeros();
+            (Reverse(size), hash_group(group, seed), group.to_string())
+        })
+        .map(|(group, rows)| {
+            // first split on ties, splits without a share never get rows
+            let split = (0..3)
+                .filter(|&split| targets[split] > 0.)
+                .max_by(|&a, &b| {
+                    let missing = |split: usize| targets[split] - assigned_rows[split] as f32;
+                    missing(a).partial_cmp(&missing(b)).unwrap().then(b.cmp(&a))
+                })
+                .unwrap_or(0);
+            assigned_rows[split] += rows;
+            (group, split as u8)
+        })
+        .collect();
+
+    groups.iter().map(|group| split_by_group[group]).collect()
+}
@@ -1,8 +1,11 @@
 use itertools::Itertools;
-use ndarray::{array, Array, Ix4, ShapeError, s};
+use ndarray::{Array, Axis, Ix4, ShapeError};
 use std::collections::BTreeMap;
 
-use crate::{datatypes::DrumTrack, map::RESOLUTION, map::NUMBER_OF_TRACKS};
+use crate::datatypes::{Bar, DrumTrack, DEFAULT_TIME_SIGNATURE};
+use crate::export::DEFAULT_PPQN;
+use crate::filter::{BarFilter, DensityFilter, GridicityFilter, DENSITY_RANGE, GRIDICITY_RANGE};
+use crate::map::Layout;
 
 #[allow(dead_code)]
 pub fn fill_stats(
@@ -11,11 +14,12 @@ pub fn fill_stats(
     key_map: &mut BTreeMap<u8, u64>,
     mut ts_count: u64,
     ts_map: &mut BTreeMap<(u8, u8, u8, u8), u64>,
+    unmapped_key_map: &mut BTreeMap<u8, u64>,
+    layout: &Layout,
 ) {
     tracks
         .iter()
-        .map(|track| track.get_key_footprint())
-        .flatten()
+        .flat_map(|track| track.get_key_footprint())
         .for_each(|key| {
             if key_map.contains_key(&key) {
                 count = key_map.get(&key).unwrap() + 1;
@@ -36,136 +40,77 @@ pub fn fill_stats(
                 ts_map.insert(ts, 1);
             }
         });
+
+    // count events whose key is not listed in the layout perc_map
+    tracks
+        .iter()
+        .flat_map(|track| track.get_unmapped_keys(&layout.perc_map))
+        .for_each(|(key, events)| {
+            *unmapped_key_map.entry(key).or_insert(0) += events as u64;
+        });
 }
 
 #[allow(dead_code)]
 pub fn display_stats(
     key_map: &BTreeMap<u8, u64>,
     ts_map: &BTreeMap<(u8, u8, u8, u8), u64>,
+    unmapped_key_map: &BTreeMap<u8, u64>,
     counter: u32,
 ) {
     key_map.iter().for_each(|(key, value)| {
         print!("[{}]: {} | ", key, value);
     });
 
-    println!("");
+    println!();
     println!("--------------- most used keys");
 
     key_map
         .iter()
-        .sorted_by(|a, b| b.1.cmp(&a.1))
+        .sorted_by(|a, b| b.1.cmp(a.1))
         .take(12)
-        .sorted_by(|a, b| b.0.cmp(&a.0))
+        .sorted_by(|a, b| b.0.cmp(a.0))
         .for_each(|(key, value)| {
             println!("KEY [{}]: {} | ", key, value);
         });
 
-    println!("");
+    println!();
     println!("--------------- time signatures");
 
     ts_map.iter().for_each(|(ts, value)| {
         println!("TS [{}/{} , {}, {}]: {} | ", ts.0, ts.1, ts.2, ts.3, value);
     });
 
-    println!("====> {} files were corrupted", counter);
-}
-
-#[allow(dead_code)]
-pub fn filter_densities(bars_array: &Array<f32, Ix4>) -> Result<Array<f32, Ix4>, ShapeError> {
-    // pure vec with filtered results
-    let mut res: Vec<f32> = vec![];
-    let mut filtered_ct = 0;
-
-    let mut highest_dens = 0.0;
-    let mut accum = 0.0;
-
-    for bar in bars_array.outer_iter() {
-        // remove offset information to calculate density
-        let vel_only = bar.slice(s![.., .., 0]);
-        let density = vel_only.mean().unwrap();
-
-        if density > 0.003 && density < 0.3 { 
+    println!();
+    println!("--------------- unmapped keys (events)");
 
-            if density > highest_dens {
-                highest_dens = density;
-            }
+    unmapped_key_map.iter().for_each(|(key, value)| {
+        println!("KEY [{}]: {} | ", key, value);
+    });
 
-            accum += density;
+    println!("====> {} files were corrupted", counter);
+}
 
-            filtered_ct += 1;
-            let v = bar.to_slice().unwrap();
-            res.extend_from_slice(v);
-        }
-    }
+// bars of bars_array kept by filter, the density and gridicity filters don't look at the track
+fn filter_bars(bars_array: &Array<f32, Ix4>, filter: &dyn BarFilter) -> Result<Array<f32, Ix4>, ShapeError> {
+    let track = DrumTrack::new(vec![], DEFAULT_TIME_SIGNATURE, DEFAULT_PPQN);
+    let kept: Vec<usize> = bars_array
+        .outer_iter()
+        .enumerate()
+        .filter(|(_, bar)| Bar::from_view(*bar).map(|bar| filter.keep(&bar, &track)).unwrap_or(false))
+        .map(|(index, _)| index)
+        .collect();
+    Ok(bars_array.select(Axis(0), &kept))
+}
 
-    println!("kept: {}, highest density {}, avg density {}", filtered_ct, highest_dens, accum / filtered_ct as f32);
-    Array::from_shape_vec((filtered_ct, RESOLUTION, NUMBER_OF_TRACKS, 2), res)
+#[deprecated(note = "use filter::DensityFilter, alone or in a filter::FilterPipeline")]
+pub fn filter_densities(bars_array: &Array<f32, Ix4>) -> Result<Array<f32, Ix4>, ShapeError> {
+    let (min, max) = DENSITY_RANGE;
+    filter_bars(bars_array, &DensityFilter { min, max })
 }
 
+#[deprecated(note = "use filter::GridicityFilter, alone or in a filter::FilterPipeline")]
 pub fn filter_gridicity(bars_array: &Array<f32, Ix4>) -> Result<Array<f32, Ix4>, ShapeError> {
-    // pure vec with filtered results
-    let mut res: Vec<f32> = vec![];
-    let mut filtered_ct = 0;
-
-    let mut velocity_kernel: Vec<f32> = Vec::new();
-
-    // kick
-    velocity_kernel.extend_from_slice(&[1.0, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1, 
-        1.0, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1, 
-        1.0, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1, 
-        1.0, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1
-    ]);
-
-    // 7 times, extends
-    for _ in 0..7 {
-        velocity_kernel.extend_from_slice(&[0.75, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1, 
-            0.75, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1, 
-            0.75, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1, 
-            0.75, 0.1, 0.25, 0.1, 0.75, 0.1, 0.25, 0.1
-        ]);
-    }
-
-    let velocity_kernel = Array::from_shape_vec((NUMBER_OF_TRACKS, RESOLUTION, 1), velocity_kernel).unwrap();
-
-    // permute 0 and 1 axis
-    let velocity_kernel = velocity_kernel.permuted_axes([1, 0, 2]);
-
-    // println!("velocity kernel shape: {:?}", velocity_kernel.shape());
-
-    // print the kernel
-    // println!("velocity kernel: {:?}", velocity_kernel);
-    for bar in bars_array.outer_iter() {
-        // get vels and offsets only
-        let vel_only = bar.slice(s![.., .., 0..1]).to_owned();
-        let offs_only = bar.slice(s![.., .., 1..2]).to_owned();
-
-        // count how many events are in vel_only > 0.05
-        let vel_evts = &vel_only.mapv(|v| {
-            if v > 0.05 {
-                1.0f32
-            } else {
-                0.0f32
-            }
-        });
-        let vel_ct = vel_evts.sum();
-
-        // apply kernel on vels
-        let vel_conv = vel_only * &velocity_kernel;
-
-        // apply a penalty for offsets
-        let offs_only = 1.0 - offs_only.mapv(f32::abs);
-
-        let vel_conv = vel_conv * offs_only;
-
-        let gridicity = vel_conv.sum() / vel_ct;
-
-        if gridicity > 0.19 && gridicity < 0.9 {
-            filtered_ct += 1;
-            let v = bar.to_slice().unwrap();
-            res.extend_from_slice(v);
-        }
-    }
-
-    println!("kept: {}, rejected: {}", filtered_ct, bars_array.shape()[0] - filtered_ct);
-    Array::from_shape_vec((filtered_ct, RESOLUTION, NUMBER_OF_TRACKS, 2), res)
-}
\ No newline at end of file
+    let (min, max) = GRIDICITY_RANGE;
+    let steps_per_bar = bars_array.shape()[1];
+    filter_bars(bars_array, &GridicityFilter { min, max, steps_per_bar })
+}
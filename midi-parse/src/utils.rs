@@ -1,31 +1,31 @@
 use itertools::Itertools;
-use midly::num::u28;
+use midly::num::u15;
+use midly::num::u24;
 use midly::num::u4;
 use midly::num::u7;
-use midly::MidiMessage;
-use midly::Smf;
+use midly::{Format, Header, MetaMessage, MidiMessage, Smf, Timing};
 use midly::TrackEvent;
 use midly::TrackEventKind;
-use std::path::{Path, PathBuf};
+use std::collections::BTreeMap;
+use std::path::Path;
 
-use crate::datatypes::{Drum, DrumTrack};
+use crate::datatypes::{Drum, DrumTrack, DEFAULT_TEMPO, DEFAULT_TIME_SIGNATURE};
+use crate::error::MidiBeatError;
+use crate::export::{to_track, DEFAULT_PPQN, META_ORDER, NOTE_OFF_ORDER, NOTE_ON_ORDER};
 
 pub fn track_has_beat_event(track: &Vec<TrackEvent>) -> bool {
     track.iter().any(|&e| match e.kind {
-        TrackEventKind::Midi {
-            channel,
-            message: _,
-        } => return channel.as_int() == 9,
-        _ => return false,
+        TrackEventKind::Midi { channel, .. } => channel.as_int() == 9,
+        _ => false,
     })
 }
 
 pub fn filter_beat_events(track: &Vec<TrackEvent>, ppqn: u16, drum_channel: bool) -> DrumTrack {
     let mut delta_count: u32 = 0;
-    let mut time_signature: (u8, u8, u8, u8) = (4, 4, 0, 0);
+    let mut time_signature: (u8, u8, u8, u8) = DEFAULT_TIME_SIGNATURE;
 
     let mut drum_events: Vec<Drum> = track
-        .into_iter()
+        .iter()
         .take_while(|e| {
             let test_counter: u64 = e.delta.as_int() as u64;
             test_counter < 100_000
@@ -34,43 +34,32 @@ pub fn filter_beat_events(track: &Vec<TrackEvent>, ppqn: u16, drum_channel: bool
             delta_count += e.delta.as_int();
 
             match e.kind {
-                TrackEventKind::Midi { channel, message } => {
-                    if channel.as_int() == 9 || !drum_channel {
-                        // println!("XCHAN: {}", channel.as_int());
-                        match message {
-                            midly::MidiMessage::NoteOn { key, vel } => {
-                                if vel.as_int() > 0 {
-                                    let drum = Drum {
-                                        time: delta_count,
-                                        key: key.as_int(),
-                                        velocity: vel.as_int(),
-                                    };
-                                    return Some(drum);
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
+                TrackEventKind::Midi {
+                    channel,
+                    message: midly::MidiMessage::NoteOn { key, vel },
+                } if (channel.as_int() == 9 || !drum_channel) && vel.as_int() > 0 => {
+                    // println!("XCHAN: {}", channel.as_int());
+                    let drum = Drum {
+                        time: delta_count,
+                        key: key.as_int(),
+                        velocity: vel.as_int(),
+                    };
+                    return Some(drum);
                 }
-                TrackEventKind::Meta(meta_message) => {
-                    match meta_message {
-                        midly::MetaMessage::TimeSignature(
-                            numerator,
-                            denominator,
-                            midi_clocks_per_click,
-                            notes_per_quarter,
-                        ) => {
-                            time_signature = (
-                                numerator,
-                                denominator,
-                                midi_clocks_per_click,
-                                notes_per_quarter,
-                            );
-                            // println!("time_signature {} / {}, midi_clocks_per_click {}, notes_per_quarter {} ", numerator, denominator, midi_clocks_per_click, notes_per_quarter)
-                            // println!("FOUND TIME SIG {:?}", time_signature);
-                        }
-                        _ => {}
-                    }
+                TrackEventKind::Meta(midly::MetaMessage::TimeSignature(
+                    numerator,
+                    denominator,
+                    midi_clocks_per_click,
+                    notes_per_quarter,
+                )) => {
+                    time_signature = (
+                        numerator,
+                        denominator,
+                        midi_clocks_per_click,
+                        notes_per_quarter,
+                    );
+                    // println!("time_signature {} / {}, midi_clocks_per_click {}, notes_per_quarter {} ", numerator, denominator, midi_clocks_per_click, notes_per_quarter)
+                    // println!("FOUND TIME SIG {:?}", time_signature);
                 }
                 _ => {}
             }
@@ -82,22 +71,63 @@ pub fn filter_beat_events(track: &Vec<TrackEvent>, ppqn: u16, drum_channel: bool
     // scale according to timesig
     // 4/4 -> x1
     // 4/2 -> x2
-    if time_signature.1 > 0 {
-        let stretch = time_signature.0 / time_signature.1;
-        if stretch > 1 {
-          for evt in drum_events.iter_mut() {
-            evt.time *= stretch as u32;
-          };
-        }
+    let stretch = get_time_stretch(time_signature);
+    if stretch > 1 {
+      for evt in drum_events.iter_mut() {
+        evt.time *= stretch;
+      };
     }
 
     DrumTrack {
         events: drum_events,
         time_signature,
         ppqn,
+        tempo: DEFAULT_TEMPO,
+        markers: vec![],
+        metadata: BTreeMap::new(),
     }
 }
 
+// time scaling applied to the events of a track of a given time signature
+pub fn get_time_stretch(time_signature: (u8, u8, u8, u8)) -> u32 {
+    time_signature
+        .0
+        .checked_div(time_signature.1)
+        .map_or(1, |stretch| stretch.max(1) as u32)
+}
+
+// marker meta events of the file with their absolute tick, (tick, text)
+pub fn get_markers(tracks: &Vec<Vec<TrackEvent>>) -> Vec<(u32, String)> {
+    tracks
+        .iter()
+        .flat_map(|track| {
+            let mut tick: u32 = 0;
+            track.iter().filter_map(move |e| {
+                tick += e.delta.as_int();
+                match e.kind {
+                    TrackEventKind::Meta(midly::MetaMessage::Marker(text)) => {
+                        Some((tick, String::from_utf8_lossy(text).trim().to_owned()))
+                    }
+                    _ => None,
+                }
+            })
+        })
+        .sorted_by_key(|(tick, _)| *tick)
+        .collect()
+}
+
+// first tempo found in the file, usually in the conductor track
+pub fn get_tempo(tracks: &Vec<Vec<TrackEvent>>) -> u32 {
+    tracks
+        .iter()
+        .flatten()
+        .find_map(|e| match e.kind {
+            TrackEventKind::Meta(midly::MetaMessage::Tempo(tempo)) => Some(tempo.as_int()),
+            _ => None,
+        })
+        .unwrap_or(DEFAULT_TEMPO)
+}
+
 pub fn get_unique_time_signature(tracks: &Vec<DrumTrack>) -> Vec<(u8, u8, u8, u8)> {
     tracks
         .iter()
@@ -120,61 +150,81 @@ pub fn key_footprints_intersect(kfp_a: Vec<u8>, kfp_b: Vec<u8>) -> bool {
     counter > 0
 }
 
-// turn smf original instance with modified tracks into midi file
-#[allow(dead_code)]
-pub fn to_smf(mut smf: Smf, tracks: &Vec<DrumTrack>, path: PathBuf) {
-    smf.tracks = tracks
-        .iter()
-        .map(|track| {
-            let mut counter: u32 = 0;
-
-            return track
-                .events
-                .clone()
-                .into_iter()
-                .map(|drum| {
-                    return vec![
-                        TrackEvent {
-                            delta: u28::try_from(drum.time).unwrap(),
-                            kind: TrackEventKind::Midi {
-                                channel: u4::try_from(9).unwrap(),
-                                message: MidiMessage::NoteOn {
-                                    key: u7::try_from(drum.key).unwrap(),
-                                    vel: u7::try_from(120).unwrap(),
-                                },
-                            },
-                        },
-                        TrackEvent {
-                            delta: u28::try_from(drum.time + 10).unwrap(),
-                            kind: TrackEventKind::Midi {
-                                channel: u4::try_from(9).unwrap(),
-                                message: MidiMessage::NoteOff {
-                                    key: u7::try_from(drum.key).unwrap(),
-                                    vel: u7::try_from(0).unwrap(),
-                                },
-                            },
-                        },
-                    ];
-                })
-                .flatten()
-                .sorted_by(|&a, b| a.delta.as_int().cmp(&b.delta.as_int()))
-                .map(|mut e| {
-                    // println!("Midi reconstructed event {}", e.delta.as_int());
-                    let int_delta = e.delta.as_int();
-                    e.delta = u28::try_from(int_delta - counter).unwrap();
-                    counter = int_delta;
-                    e
-                })
-                .collect();
-        })
-        .collect();
+// ticks between note on and note off of the exported tracks, a 32nd note
+fn get_debug_note_length(ppqn: u16) -> u32 {
+    (ppqn as u32 / 8).max(1)
+}
+
+// SMF of parsed tracks, one MIDI track per drum track with its original velocities, tempo,
+// time signature and markers, the time scaling of the time signature is undone. Tracks which had
+// no time signature event get none, DEFAULT_TIME_SIGNATURE isn't a valid event
+pub fn to_smf(tracks: &[DrumTrack]) -> Result<Smf<'_>, MidiBeatError> {
+    let out_of_range = |field: &'static str, value: u32| MidiBeatError::OutOfRange { field, value };
+
+    let ppqn = tracks.first().map(|track| track.ppqn).unwrap_or(DEFAULT_PPQN);
+    let ppqn = u15::try_from(ppqn).ok_or_else(|| out_of_range("ppqn", ppqn as u32))?;
+    let note_length = get_debug_note_length(ppqn.as_int());
+
+    let mut smf = Smf::new(Header::new(Format::Parallel, Timing::Metrical(ppqn)));
+
+    for (index, track) in tracks.iter().enumerate() {
+        let stretch = get_time_stretch(track.time_signature);
+        let tempo = u24::try_from(track.tempo).ok_or_else(|| out_of_range("tempo", track.tempo))?;
+        let (numerator, denominator, clocks_per_click, notes_per_quarter) = track.time_signature;
+
+        // (tick, order, event)
+        let mut events: Vec<(u32, u8, TrackEventKind)> =
+            vec![(0, META_ORDER, TrackEventKind::Meta(MetaMessage::Tempo(tempo)))];
+        if track.time_signature != DEFAULT_TIME_SIGNATURE {
+            events.push((
+                0,
+                META_ORDER,
+                TrackEventKind::Meta(MetaMessage::TimeSignature(
+                    numerator,
+                    denominator,
+                    clocks_per_click,
+                    notes_per_quarter,
+                )),
+            ));
+        }
+
+        // markers belong to the file, they are written once
+        if index == 0 {
+            events.extend(
+                track
+                    .markers
+                    .iter()
+                    .map(|(tick, text)| (tick / stretch, META_ORDER, TrackEventKind::Meta(MetaMessage::Marker(text.as_bytes())))),
+            );
+        }
+
+        for drum in track.events.iter() {
+            let key = u7::try_from(drum.key).ok_or_else(|| out_of_range("key", drum.key as u32))?;
+            let vel = u7::try_from(drum.velocity).ok_or_else(|| out_of_range("velocity", drum.velocity as u32))?;
+            let tick = drum.time / stretch;
+            let channel = u4::new(9);
+
+            events.push((tick, NOTE_ON_ORDER, TrackEventKind::Midi { channel, message: MidiMessage::NoteOn { key, vel } }));
+            events.push((
+                tick + note_length,
+                NOTE_OFF_ORDER,
+                TrackEventKind::Midi {
+                    channel,
+                    message: MidiMessage::NoteOff { key, vel: u7::new(0) },
+                },
+            ));
+        }
+
+        smf.tracks.push(to_track(events)?);
+    }
 
-    let prepend_str: &str = "./generated_";
-    let file_name = path.file_name().unwrap().to_str().unwrap();
-    let new_name = format!("{}{}", prepend_str, file_name);
-    let new_path = Path::new(&new_name);
+    Ok(smf)
+}
 
-    smf.save(new_path).unwrap();
+// writes parsed tracks to a MIDI file, to check what the parser kept from a file
+pub fn write_tracks<P: AsRef<Path>>(path: P, tracks: &[DrumTrack]) -> Result<(), MidiBeatError> {
+    to_smf(tracks)?.save(path)?;
+    Ok(())
 }
 
 pub fn div_rem<T: std::ops::Div<Output = T> + std::ops::Rem<Output = T> + Copy>(
@@ -197,3 +247,11 @@ pub fn normalize_velocity(vel: usize) -> f32 {
 pub fn normalize_offset(ticks_offset: isize, step_tick_duration: usize) -> f32 {
     ticks_offset as f32 / step_tick_duration as f32
 }
+
+// FNV-1a 64 of some content as hex, stable across platforms and Rust versions
+pub fn content_hash(bytes: &[u8]) -> String {
+    let hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325u64, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    });
+    format!("{:016x}", hash)
+}
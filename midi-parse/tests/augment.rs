@@ -0,0 +1,98 @@
+mod common;
+
+use common::groove;
+use midi_parse::augment::{augment_bars, parse_augmentations, Augmentation, Rng};
+use midi_parse::datatypes::Bar;
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT, RESOLUTION};
+
+fn hit_steps(bar: &Bar, lane: usize) -> Vec<usize> {
+    (0..bar.number_of_steps()).filter(|&step| bar.hit(step, lane)).collect()
+}
+
+#[test]
+fn swing_delays_the_off_beats() {
+    let mut bar = Bar::new(RESOLUTION, 8);
+    for step in (0..RESOLUTION).step_by(2) {
+        bar.set(step, 5, 0.5, 0.);
+    }
+
+    // 16th swing: pairs of 4 steps, the off-beat 16th moves a third of a 16th later
+    let swung = Augmentation::Swing { amount: 1., pairs_per_bar: 8 }.apply(&[bar], &mut Rng::new(0));
+    let swung = &swung[0];
+
+    let expected: Vec<usize> = (0..RESOLUTION / 4).flat_map(|pair| vec![pair * 4, pair * 4 + 3]).collect();
+    assert_eq!(hit_steps(swung, 5), expected);
+    for pair in 0..RESOLUTION / 4 {
+        assert_eq!(swung.offset(pair * 4, 5), 0.);
+        assert!((swung.offset(pair * 4 + 3, 5) + 1. / 3.).abs() < 1e-5);
+    }
+}
+
+#[test]
+fn half_and_double_time_move_the_hits() {
+    let mut bar = Bar::new(RESOLUTION, 8);
+    bar.set(4, 0, 1., 0.);
+    bar.set(10, 1, 0.8, 0.25);
+    // second half, out of the half time bar
+    bar.set(20, 1, 0.8, 0.);
+
+    let half = &Augmentation::HalfTime.apply(&[bar.clone()], &mut Rng::new(0))[0];
+    assert_eq!(hit_steps(half, 0), vec![8]);
+    // 2 * 10.25 = 20.5, rounded away from the step
+    assert_eq!(hit_steps(half, 1), vec![21]);
+    assert!((half.offset(21, 1) + 0.5).abs() < 1e-5);
+    assert_eq!(half.number_of_hits(), 2);
+
+    let double = &Augmentation::DoubleTime.apply(&[bar], &mut Rng::new(0))[0];
+    assert_eq!(hit_steps(double, 0), vec![2, 18]);
+    assert_eq!(hit_steps(double, 1), vec![5, 10, 21, 26]);
+    assert!((double.offset(5, 1) - 0.125).abs() < 1e-5);
+}
+
+#[test]
+fn lane_dropout_never_silences_a_row() {
+    let bars = vec![groove(), groove()];
+
+    // every lane dropped, the row is kept as it is
+    let dropped = Augmentation::LaneDropout { probability: 1. }.apply(&bars, &mut Rng::new(0));
+    assert_eq!(dropped[0].view(), bars[0].view());
+
+    let dropout = Augmentation::LaneDropout { probability: 0.9 };
+    let mut rng = Rng::new(1);
+    for _ in 0..200 {
+        let dropped = dropout.apply(&bars, &mut rng);
+        assert!(dropped.iter().any(|bar| !bar.is_silent()));
+        // lanes are dropped for the whole row
+        assert_eq!(dropped[0].view(), dropped[1].view());
+    }
+}
+
+#[test]
+fn same_seed_same_augmentations() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let chain = parse_augmentations("velocity_scale=0.7:1.2,timing_jitter=0.2,lane_dropout=0.3", &layout).unwrap();
+    let bars = vec![groove(), groove()];
+
+    let first = augment_bars(&bars, &chain, &mut Rng::new(42));
+    let second = augment_bars(&bars, &chain, &mut Rng::new(42));
+    let other = augment_bars(&bars, &chain, &mut Rng::new(43));
+
+    for (a, b) in first.iter().zip(second.iter()) {
+        assert_eq!(a.view(), b.view());
+    }
+    assert!(first.iter().zip(other.iter()).any(|(a, b)| a.view() != b.view()));
+}
+
+#[test]
+fn swaps_take_lane_names_or_indexes() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+
+    assert_eq!(layout.lane_index("ride"), Some(7));
+    assert_eq!(layout.lane_index(" 5 "), Some(5));
+    assert_eq!(layout.lane_index("8"), None);
+    assert_eq!(
+        Augmentation::parse("swap=closed_hh:7", &layout).unwrap(),
+        Augmentation::LaneSwap { a: 5, b: 7 }
+    );
+    assert!(Augmentation::parse("swap=closed_hh:cowbell", &layout).is_err());
+}
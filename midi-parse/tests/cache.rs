@@ -0,0 +1,81 @@
+#![cfg(feature = "serialization")]
+
+mod common;
+
+use std::fs;
+use std::path::PathBuf;
+
+use common::{groove, kick_bar};
+use midi_parse::cache::ParseCache;
+use midi_parse::diagnostics::{Diagnostics, Level};
+use midi_parse::export::{bars_to_smf, ExportSettings};
+use midi_parse::gmd::GMD_RESOLUTION;
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT, RESOLUTION};
+use midi_parse::serialization::to_binary;
+use midi_parse::utils::content_hash;
+
+fn cache_directory(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("midi-parse-cache-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    directory
+}
+
+fn midi_bytes() -> Vec<u8> {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut bytes: Vec<u8> = vec![];
+    bars_to_smf(&[groove(), kick_bar(3), groove()], &ExportSettings::new(&layout))
+        .unwrap()
+        .write(&mut bytes)
+        .unwrap();
+    bytes
+}
+
+#[test]
+fn parsed_files_and_parse_errors_are_reused() {
+    let mut cache = ParseCache::new(cache_directory("parse")).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let bytes = midi_bytes();
+
+    let parsed = cache.parse(&bytes, true, &mut diagnostics).unwrap();
+    let reused = cache.parse(&bytes, true, &mut diagnostics).unwrap();
+    assert_eq!(reused, parsed);
+    assert_eq!((cache.hits, cache.misses), (1, 1));
+
+    // not a MIDI file, the error is cached like tracks
+    let error = cache.parse(b"MThd", true, &mut diagnostics).unwrap_err().to_string();
+    let cached_error = cache.parse(b"MThd", true, &mut diagnostics).unwrap_err().to_string();
+    assert_eq!(cached_error, error);
+    assert_eq!((cache.hits, cache.misses), (2, 2));
+    assert_eq!(diagnostics.count(Level::Warning), 0);
+}
+
+#[test]
+fn grids_are_keyed_by_layout_resolution_and_drum_channel() {
+    let mut cache = ParseCache::new(cache_directory("grids")).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let bytes = midi_bytes();
+    let hash = content_hash(&bytes);
+    let tracks = cache.parse(&bytes, true, &mut diagnostics).unwrap();
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+
+    let gridded = cache.grids(&hash, true, &tracks, &layout, RESOLUTION, &mut diagnostics);
+    let reused = cache.grids(&hash, true, &tracks, &layout, RESOLUTION, &mut diagnostics);
+    assert_eq!((cache.grid_hits, cache.grid_misses), (1, 1));
+
+    assert_eq!(gridded.len(), 1);
+    assert_eq!(reused.len(), gridded.len());
+    for (reused, gridded) in reused.iter().zip(gridded.iter()) {
+        let (reused, gridded) = (reused.as_ref().unwrap(), gridded.as_ref().unwrap());
+        assert_eq!(reused.track_index, gridded.track_index);
+        assert_eq!(reused.track_perc_map, gridded.track_perc_map);
+        assert_eq!(to_binary(&reused.bars).unwrap(), to_binary(&gridded.bars).unwrap());
+    }
+
+    // every other key is a new entry
+    cache.grids(&hash, true, &tracks, &layout, GMD_RESOLUTION, &mut diagnostics);
+    cache.grids(&hash, false, &tracks, &layout, RESOLUTION, &mut diagnostics);
+    let mut merged = layout.clone();
+    merged.merge_keys = true;
+    cache.grids(&hash, true, &tracks, &merged, RESOLUTION, &mut diagnostics);
+    assert_eq!((cache.grid_hits, cache.grid_misses), (1, 4));
+}
@@ -0,0 +1,37 @@
+// fixtures shared by the integration tests, each test crate only uses some of them
+#![allow(dead_code)]
+
+use midi_parse::datatypes::Bar;
+use midi_parse::map::{TrackGrid, NUMBER_OF_TRACKS, RESOLUTION};
+
+// default layout bar with a single kick, bars kicking on different steps are different bars
+pub fn kick_bar(step: usize) -> Bar {
+    let mut bar = Bar::new(RESOLUTION, NUMBER_OF_TRACKS);
+    bar.set(step, 0, 1., 0.);
+    bar
+}
+
+// closed hh on every 8th, kick on 1 and 3, snare on 2 and 4
+pub fn groove() -> Bar {
+    let mut bar = Bar::new(RESOLUTION, NUMBER_OF_TRACKS);
+    for step in (0..RESOLUTION).step_by(4) {
+        bar.set(step, 5, 0.6, 0.);
+    }
+    bar.set(0, 0, 1., 0.);
+    bar.set(16, 0, 1., 0.);
+    bar.set(8, 1, 0.9, 0.);
+    bar.set(24, 1, 0.9, 0.);
+    bar
+}
+
+pub fn silent_bar() -> Bar {
+    Bar::new(RESOLUTION, NUMBER_OF_TRACKS)
+}
+
+pub fn track_grid(track_index: usize, bars: Vec<Bar>) -> TrackGrid {
+    TrackGrid {
+        track_index,
+        track_perc_map: vec![None; NUMBER_OF_TRACKS],
+        bars,
+    }
+}
@@ -0,0 +1,96 @@
+use midly::{MetaMessage, TrackEventKind};
+
+use midi_parse::datatypes::{Bar, Drum, DrumTrack, DEFAULT_TIME_SIGNATURE};
+use midi_parse::export::{bars_to_smf, ExportSettings};
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT, RESOLUTION};
+use midi_parse::parse::parse_bytes;
+use midi_parse::utils::to_smf;
+
+#[test]
+fn grid_smf_grid_round_trip() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let settings = ExportSettings::new(&layout);
+
+    // MIDI velocities, offsets in multiples of a tick (60 ticks per step) within half a step,
+    // the parser puts a hit exactly between two steps late on the first one
+    let mut first = Bar::new(RESOLUTION, 8);
+    first.set(0, 0, 127. / 127., 0.);
+    first.set(8, 1, 100. / 127., 0.25);
+    first.set(16, 0, 90. / 127., -0.25);
+    for step in (0..RESOLUTION).step_by(4) {
+        first.set(step, 5, 64. / 127., 0.);
+    }
+    let mut second = Bar::new(RESOLUTION, 8);
+    second.set(0, 6, 110. / 127., 0.);
+    second.set(12, 2, 70. / 127., 0.1);
+    // not the last step, the parser drops hits of the last step of a track
+    second.set(28, 7, 50. / 127., 0.);
+
+    let mut bytes: Vec<u8> = vec![];
+    bars_to_smf(&[first.clone(), second.clone()], &settings)
+        .unwrap()
+        .write(&mut bytes)
+        .unwrap();
+    let tracks = parse_bytes(&bytes, true).unwrap();
+    assert_eq!(tracks.len(), 1);
+    assert_eq!(tracks[0].time_signature.0, 4);
+
+    let track_perc_map = tracks[0].get_track_perc_map_with(&layout.perc_map, &layout.alt_map);
+    let bars = tracks[0].to_grid(&track_perc_map).unwrap();
+
+    assert_eq!(bars.len(), 2);
+    for (bar, expected) in bars.iter().zip([first, second].iter()) {
+        assert_eq!(bar.number_of_hits(), expected.number_of_hits());
+        for step in 0..RESOLUTION {
+            for lane in 0..8 {
+                assert_eq!(bar.hit(step, lane), expected.hit(step, lane), "step {} lane {}", step, lane);
+                assert!((bar.velocity(step, lane) - expected.velocity(step, lane)).abs() < 1e-5);
+                assert!((bar.offset(step, lane) - expected.offset(step, lane)).abs() < 1e-5);
+            }
+        }
+    }
+}
+
+#[test]
+fn dumped_tracks_parse_back_to_the_same_tracks() {
+    let events = |stretch: u32| -> Vec<Drum> {
+        (0..16)
+            .map(|step| Drum {
+                time: step * 120 * stretch,
+                velocity: 40 + step as u8 * 5,
+                key: if step % 4 == 0 { 36 } else { 42 },
+            })
+            .collect()
+    };
+    // a file without time signature event, and a 4/4 one, whose ticks are stretched by the parser
+    let tracks = vec![
+        DrumTrack::new(events(1), DEFAULT_TIME_SIGNATURE, 480),
+        DrumTrack::new(events(2), (4, 2, 24, 8), 480),
+    ];
+
+    for track in tracks.into_iter() {
+        let smf = to_smf(std::slice::from_ref(&track)).unwrap();
+        // DEFAULT_TIME_SIGNATURE would read as 4/16 in other tools
+        let time_signatures: Vec<(u8, u8, u8, u8)> = smf.tracks[0]
+            .iter()
+            .filter_map(|event| match event.kind {
+                TrackEventKind::Meta(MetaMessage::TimeSignature(a, b, c, d)) => Some((a, b, c, d)),
+                _ => None,
+            })
+            .collect();
+        if track.time_signature == DEFAULT_TIME_SIGNATURE {
+            assert!(time_signatures.is_empty());
+        } else {
+            assert_eq!(time_signatures, vec![track.time_signature]);
+        }
+
+        let mut bytes: Vec<u8> = vec![];
+        smf.write(&mut bytes).unwrap();
+        let parsed = parse_bytes(&bytes, true).unwrap();
+
+        assert_eq!(parsed.len(), 1);
+        assert_eq!(parsed[0].time_signature, track.time_signature);
+        assert_eq!(parsed[0].tempo, track.tempo);
+        assert_eq!(parsed[0].events, track.events);
+    }
+}
@@ -0,0 +1,56 @@
+use midi_parse::datatypes::{Drum, DrumTrack};
+use midi_parse::fidelity::measure_track;
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT};
+
+const RESOLUTION: usize = 16;
+
+fn drum(time: usize, key: u8, velocity: u8) -> Drum {
+    Drum {
+        time: time as u32,
+        velocity,
+        key,
+    }
+}
+
+#[test]
+fn round_trip_counts_collisions_and_last_step_drops() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut track = DrumTrack::new(vec![], (4, 2, 24, 8), 480);
+    let step = track.get_step_track_duration_with(RESOLUTION);
+
+    let mut events = vec![
+        drum(0, 36, 100),
+        // late kick, the grid keeps its offset
+        drum(8 * step + step / 4, 36, 90),
+        // three snares on a same cell, the second one takes the next step back with a -1 offset,
+        // the third one is lost
+        drum(4 * step, 38, 100),
+        drum(4 * step, 38, 60),
+        drum(4 * step, 38, 40),
+        drum(12 * step, 38, 80),
+    ];
+    // closed hh on every 8th, then one on the last step of the track, which the grid never fills
+    events.extend((0..RESOLUTION).step_by(2).map(|hh_step| drum(hh_step * step, 42, 70)));
+    events.push(drum(15 * step, 42, 50));
+    events.sort_by_key(|drum| drum.time);
+    track.events = events;
+
+    let fidelity = measure_track(&track, &layout, RESOLUTION).unwrap();
+
+    assert_eq!(fidelity.rejected_tracks, 0);
+    assert_eq!(fidelity.events, 15);
+    assert_eq!(fidelity.collisions, 1);
+    assert_eq!(fidelity.last_step, 1);
+    assert_eq!(fidelity.unmapped, 0);
+    assert_eq!(fidelity.kept, 13);
+    // kept hits come back on their ticks with their velocities
+    assert_eq!(fidelity.timing_error_ticks, 0.);
+    assert_eq!(fidelity.max_velocity_error, 0);
+
+    let kicks = &fidelity.lanes[0];
+    assert_eq!((kicks.events, kicks.kept), (2, 2));
+    let snares = &fidelity.lanes[1];
+    assert_eq!((snares.events, snares.kept), (4, 3));
+    let closed_hh = &fidelity.lanes[5];
+    assert_eq!((closed_hh.events, closed_hh.kept), (9, 8));
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::groove;
+use midi_parse::datatypes::Bar;
+use midi_parse::fill::{
+    get_fill_labels, get_fill_lanes, get_fill_scores, get_row_fill_scores, FILL_LABEL, FILL_THRESHOLD, GROOVE_LABEL,
+};
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT, RESOLUTION};
+use midi_parse::provenance::BarSource;
+
+// default layout lanes: kick, snare, low, mid and high toms, closed hh, open_hh_crash, ride
+fn track_perc_map(open_hh_crash_key: u8) -> Vec<Option<u8>> {
+    vec![Some(36), Some(38), Some(45), Some(47), Some(50), Some(42), Some(open_hh_crash_key), Some(51)]
+}
+
+// kick on the downbeat then a run down the toms
+fn tom_fill() -> Bar {
+    let mut bar = Bar::new(RESOLUTION, 8);
+    bar.set(0, 0, 1., 0.);
+    for (index, step) in (16..RESOLUTION).step_by(2).enumerate() {
+        bar.set(step, 4 - index / 3 % 3, 0.8, 0.);
+    }
+    bar
+}
+
+// 7 groove bars, a fill ending the 8 bars phrase, then a groove with a cymbal on the downbeat
+fn phrase() -> Vec<Bar> {
+    let mut bars: Vec<Bar> = (0..7).map(|_| groove()).collect();
+    bars.push(tom_fill());
+    let mut landing = groove();
+    landing.set(0, 6, 1., 0.);
+    bars.push(landing);
+    bars.push(Bar::new(RESOLUTION, 8));
+    bars
+}
+
+#[test]
+fn open_hh_crash_lane_is_a_crash_only_when_a_crash_plays_it() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+
+    let open_hat = get_fill_lanes(&layout, &track_perc_map(46));
+    assert_eq!(open_hat.toms, vec![2, 3, 4]);
+    assert!(open_hat.crashes.is_empty());
+
+    let crash = get_fill_lanes(&layout, &track_perc_map(49));
+    assert_eq!(crash.crashes, vec![6]);
+
+    // lanes without a key in the track are neither
+    let mut empty_toms = track_perc_map(49);
+    empty_toms[3] = None;
+    assert_eq!(get_fill_lanes(&layout, &empty_toms).toms, vec![2, 4]);
+}
+
+#[test]
+fn tom_fill_ending_a_phrase_is_a_fill() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let bars = phrase();
+
+    let scores = get_fill_scores(&bars, &get_fill_lanes(&layout, &track_perc_map(49)));
+    assert_eq!(scores.len(), bars.len());
+    assert!(scores[7] >= FILL_THRESHOLD, "fill score {}", scores[7]);
+    for (bar_index, score) in scores.iter().enumerate().filter(|&(bar_index, _)| bar_index != 7) {
+        assert!(*score < FILL_THRESHOLD, "bar {} score {}", bar_index, score);
+    }
+    // silent bars never score
+    assert_eq!(scores[9], 0.);
+
+    // the landing cymbal only counts when it's a crash
+    let open_hat_scores = get_fill_scores(&bars, &get_fill_lanes(&layout, &track_perc_map(46)));
+    assert!((scores[7] - open_hat_scores[7] - 0.15).abs() < 1e-5);
+    assert_eq!(scores[6], open_hat_scores[6]);
+}
+
+#[test]
+fn rows_take_the_highest_score_of_their_bars() {
+    let fill_scores: HashMap<usize, Vec<f32>> = vec![(3, vec![0.1, 0.2, 0.9, 0.3])].into_iter().collect();
+    let sources = vec![
+        BarSource { track_index: 3, bar_index: 0 },
+        BarSource { track_index: 3, bar_index: 1 },
+        BarSource { track_index: 3, bar_index: 3 },
+        // tracks without scores
+        BarSource { track_index: 5, bar_index: 0 },
+    ];
+
+    let single_bars = get_row_fill_scores(&fill_scores, &sources, 0, 1);
+    assert_eq!(single_bars.to_vec(), vec![0.1, 0.2, 0.3, 0.]);
+
+    // 2 bars windows never read past the end of the track
+    let windows = get_row_fill_scores(&fill_scores, &sources, 0, 2);
+    assert_eq!(windows.to_vec(), vec![0.2, 0.9, 0.3, 0.]);
+
+    // pair targets, one bar after a 1 bar context
+    let targets = get_row_fill_scores(&fill_scores, &sources, 1, 1);
+    assert_eq!(targets.to_vec(), vec![0.2, 0.9, 0., 0.]);
+
+    assert_eq!(get_fill_labels(&windows).to_vec(), vec![GROOVE_LABEL, FILL_LABEL, GROOVE_LABEL, GROOVE_LABEL]);
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use ndarray::{stack, Array, Axis, Ix4};
+
+use common::groove;
+use midi_parse::datatypes::{Bar, DrumTrack, DEFAULT_TIME_SIGNATURE};
+use midi_parse::diagnostics::{Diagnostics, Level};
+use midi_parse::export::{bars_to_smf, ExportSettings};
+use midi_parse::filter::{parse_filter, FilterPipeline};
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT, RESOLUTION};
+use midi_parse::parse::parse_bytes;
+use midi_parse::stats;
+
+// track of a 4/4 file, as the parser reads it
+fn four_four_track() -> DrumTrack {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut bytes: Vec<u8> = vec![];
+    bars_to_smf(&[groove()], &ExportSettings::new(&layout))
+        .unwrap()
+        .write(&mut bytes)
+        .unwrap();
+    parse_bytes(&bytes, true).unwrap().remove(0)
+}
+
+#[test]
+fn meter_filter_reads_time_signatures_as_written() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let track = four_four_track();
+    let bar = groove();
+
+    let keeps = |spec: &str| parse_filter(spec, &layout, RESOLUTION).unwrap().keep(&bar, &track);
+    assert!(keeps("meter=4/4"));
+    assert!(keeps("meter=3/4, 4/4"));
+    assert!(!keeps("meter=4/2"));
+    assert!(!keeps("meter=2/4"));
+    assert!(parse_filter("meter=4/3", &layout, RESOLUTION).is_err());
+
+    // files without a time signature event are 4/4
+    let mut no_time_signature = track.clone();
+    no_time_signature.time_signature = DEFAULT_TIME_SIGNATURE;
+    let filter = parse_filter("meter=4/4", &layout, RESOLUTION).unwrap();
+    assert!(filter.keep(&bar, &no_time_signature));
+}
+
+#[test]
+fn pipeline_counts_each_filter_on_the_rows_left_by_the_previous_ones() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let track = four_four_track();
+    let silent = Bar::new(RESOLUTION, 8);
+    let mut kick_only = Bar::new(RESOLUTION, 8);
+    kick_only.set(0, 0, 1., 0.);
+    kick_only.set(16, 0, 1., 0.);
+
+    let rows = [groove(), silent, kick_only, groove()];
+    let views: Vec<_> = rows.iter().map(|bar| bar.view()).collect();
+    let array: Array<f32, Ix4> = stack(Axis(0), &views).unwrap();
+    // the last row has no track
+    let tracks = vec![&track, &track, &track];
+
+    let specs = vec!["density".to_owned(), "lanes=snare".to_owned(), "meter=4/4".to_owned()];
+    let pipeline = FilterPipeline::parse(&specs, &layout, RESOLUTION).unwrap();
+    let mut diagnostics = Diagnostics::new();
+
+    assert_eq!(pipeline.kept_indices(&array, &tracks, &mut diagnostics), vec![0]);
+    assert_eq!(diagnostics.count(Level::Warning), 1);
+    let messages: Vec<&str> = diagnostics
+        .diagnostics
+        .iter()
+        .filter(|diagnostic| diagnostic.level == Level::Info)
+        .map(|diagnostic| diagnostic.message.as_str())
+        .collect();
+    assert_eq!(
+        messages,
+        vec!["density kept: 2, rejected: 1", "lanes=snare kept: 1, rejected: 1", "meter=4/4 kept: 1, rejected: 0"]
+    );
+}
+
+#[test]
+#[allow(deprecated)]
+fn deprecated_stats_filters_keep_what_the_pipeline_keeps() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let track = four_four_track();
+    let mut late = groove();
+    for step in (0..RESOLUTION).step_by(4) {
+        late.set(step, 5, 0.6, 0.45);
+    }
+
+    let rows = [groove(), Bar::new(RESOLUTION, 8), late];
+    let views: Vec<_> = rows.iter().map(|bar| bar.view()).collect();
+    let array: Array<f32, Ix4> = stack(Axis(0), &views).unwrap();
+    let tracks = vec![&track; rows.len()];
+
+    for (spec, filtered) in [("density", stats::filter_densities(&array)), ("gridicity", stats::filter_gridicity(&array))].iter() {
+        let pipeline = FilterPipeline::parse(&[spec.to_string()], &layout, RESOLUTION).unwrap();
+        let kept = pipeline.kept_indices(&array, &tracks, &mut Diagnostics::new());
+        assert!(kept.len() < rows.len(), "{}", spec);
+        assert_eq!(filtered.as_ref().unwrap(), &array.select(Axis(0), &kept), "{}", spec);
+    }
+}
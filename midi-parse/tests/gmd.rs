@@ -0,0 +1,85 @@
+use ndarray::Array;
+
+use midi_parse::datatypes::{Drum, DrumTrack};
+use midi_parse::diagnostics::Diagnostics;
+use midi_parse::gmd::{check_gmd_layout, process_track_pool_gmd, to_gmd_tensors, GMD_LANES, GMD_RESOLUTION};
+use midi_parse::map::{get_layout, get_track_pool_grids, DEFAULT_LAYOUT};
+use midi_parse::utils::normalize_velocity;
+
+#[test]
+fn offsets_out_of_range_move_to_the_neighbouring_step() {
+    let mut windows = Array::<f32, _>::zeros((1, GMD_RESOLUTION, GMD_LANES, 2));
+    // lane 0: late hit, its neighbouring step is free
+    windows[[0, 3, 0, 0]] = 0.5;
+    windows[[0, 3, 0, 1]] = 0.8;
+    // lane 1: early hit, its neighbouring step holds a louder hit
+    windows[[0, 6, 1, 0]] = 0.9;
+    windows[[0, 7, 1, 0]] = 0.4;
+    windows[[0, 7, 1, 1]] = -0.7;
+    // lane 2: late hit on the last step, nowhere to go
+    windows[[0, 15, 2, 0]] = 0.6;
+    windows[[0, 15, 2, 1]] = 0.9;
+
+    let tensors = to_gmd_tensors(windows.view());
+
+    assert_eq!(tensors.hits[[0, 3, 0]], 0.);
+    assert_eq!(tensors.velocities[[0, 4, 0]], 0.5);
+    assert!((tensors.offsets[[0, 4, 0]] + 0.2).abs() < 1e-6);
+
+    assert_eq!(tensors.hits[[0, 7, 1]], 0.);
+    assert_eq!(tensors.velocities[[0, 6, 1]], 0.9);
+    assert_eq!(tensors.offsets[[0, 6, 1]], 0.);
+
+    assert_eq!(tensors.offsets[[0, 15, 2]], 0.5);
+    assert!(tensors.offsets.iter().all(|offset| offset.abs() <= 0.5));
+}
+
+#[test]
+fn gmd_needs_nine_lanes() {
+    assert!(check_gmd_layout(&get_layout("gmd9").unwrap()).is_ok());
+    assert!(check_gmd_layout(&get_layout(DEFAULT_LAYOUT).unwrap()).is_err());
+}
+
+#[test]
+fn every_key_of_a_class_lands_on_its_lane() {
+    let mut track = DrumTrack::new(vec![], (4, 2, 24, 8), 480);
+    let step_ticks = track.get_step_track_duration_with(GMD_RESOLUTION) as u32;
+    let step = |step: u32| step * step_ticks;
+    let drum = |time: u32, key: u8, velocity: u8| Drum { time, velocity, key };
+    track.events = vec![
+        drum(step(0), 38, 100),
+        drum(step(2), 42, 80),
+        drum(step(4), 40, 90),
+        drum(step(6), 44, 70),
+        // two keys of a class on a same tick, the loudest wins
+        drum(step(8), 38, 60),
+        drum(step(8), 40, 110),
+        drum(step(10), 42, 50),
+        drum(step(10), 44, 100),
+        drum(step(16), 36, 100),
+        drum(step(30), 36, 100),
+    ];
+    let track_pool = vec![track];
+    let layout = get_layout("gmd9").unwrap();
+    let mut diagnostics = Diagnostics::new();
+
+    let track_grids = get_track_pool_grids(&track_pool, &layout, GMD_RESOLUTION, &mut diagnostics);
+    let (windows, _) = process_track_pool_gmd(&track_grids, &layout, 2, 1, &mut diagnostics).unwrap();
+    let tensors = to_gmd_tensors(windows.view());
+
+    let (snare, closed_hh) = (1, 2);
+    for &(step, lane, velocity) in [
+        (0, snare, 100),
+        (4, snare, 90),
+        (8, snare, 110),
+        (2, closed_hh, 80),
+        (6, closed_hh, 70),
+        (10, closed_hh, 100),
+    ]
+    .iter()
+    {
+        assert_eq!(tensors.hits[[0, step, lane]], 1.);
+        assert_eq!(tensors.velocities[[0, step, lane]], normalize_velocity(velocity));
+    }
+    assert_eq!(tensors.hits.sum(), 8.);
+}
@@ -0,0 +1,66 @@
+use midi_parse::datatypes::{Drum, DrumTrack};
+use midi_parse::learn::KeyStatsCollector;
+use midi_parse::map::Layout;
+
+const PPQN: u16 = 480;
+// 32nd note
+const STEP: u32 = 60;
+
+// 4 bars of 4/4, the key is hit on every listed step of each bar
+fn track(parts: &[(u8, &[u32])]) -> DrumTrack {
+    let mut events: Vec<Drum> = (0..4)
+        .flat_map(|bar| {
+            parts.iter().flat_map(move |(key, steps)| {
+                steps.iter().map(move |step| Drum {
+                    time: (bar * 32 + step) * STEP,
+                    velocity: 100,
+                    key: *key,
+                })
+            })
+        })
+        .collect();
+    events.sort_by_key(|drum| drum.time);
+    DrumTrack::new(events, (4, 4, 24, 8), PPQN)
+}
+
+fn collector() -> KeyStatsCollector {
+    let mut collector = KeyStatsCollector::new();
+    for _ in 0..5 {
+        // 36 and 35 play the same role but never together
+        collector.add_track(&track(&[(36, &[0, 16]), (38, &[8, 24]), (42, &[0, 4, 8, 12, 16, 20, 24, 28])]));
+        collector.add_track(&track(&[(35, &[0, 16]), (38, &[8, 24]), (42, &[0, 4, 8, 12, 16, 20, 24, 28])]));
+    }
+    // a single crash
+    collector.add_track(&track(&[(36, &[0, 16]), (49, &[0])]));
+    collector
+}
+
+fn lane_of(layout: &Layout, key: u8) -> Option<usize> {
+    layout.perc_map.iter().position(|keys| keys.contains(&key))
+}
+
+#[test]
+fn keys_playing_the_same_role_share_a_lane() {
+    let layout = collector().learn_layout("learned", 3, 10);
+
+    assert_eq!(layout.number_of_lanes(), 3);
+    assert_eq!(lane_of(&layout, 36), lane_of(&layout, 35));
+    assert_ne!(lane_of(&layout, 36), lane_of(&layout, 38));
+    assert_ne!(lane_of(&layout, 42), lane_of(&layout, 38));
+    assert_ne!(lane_of(&layout, 42), lane_of(&layout, 36));
+}
+
+#[test]
+fn rare_keys_are_fallback_only() {
+    let layout = collector().learn_layout("learned", 3, 10);
+
+    assert_eq!(lane_of(&layout, 49), None);
+    let groups = &layout.alt_map[&49];
+    assert!(!groups.is_empty() && groups.iter().all(|&lane| lane < layout.number_of_lanes()));
+
+    let path = std::env::temp_dir().join("midi_parse_learned_layout.csv");
+    layout.to_csv(path.to_str().unwrap()).unwrap();
+    let read = Layout::from_csv(path.to_str().unwrap()).unwrap();
+    assert_eq!(read.perc_map, layout.perc_map);
+    assert_eq!(read.alt_map[&49], *groups);
+}
@@ -0,0 +1,54 @@
+mod common;
+
+use common::{kick_bar, silent_bar, track_grid};
+use midi_parse::datatypes::VELOCITY;
+use midi_parse::diagnostics::Diagnostics;
+use midi_parse::map::{get_layout, process_track_pool_pairs, DEFAULT_LAYOUT, NUMBER_OF_TRACKS, RESOLUTION};
+
+#[test]
+fn pairs_hold_the_context_bars_and_the_next_bar() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let track_grids = vec![track_grid(0, (0..4).map(kick_bar).collect())];
+
+    let (contexts, targets, sources) =
+        process_track_pool_pairs(&track_grids, &layout, 2, false, &mut diagnostics).unwrap();
+
+    assert_eq!(contexts.shape(), &[2, 2 * RESOLUTION, NUMBER_OF_TRACKS, 2]);
+    assert_eq!(targets.shape(), &[2, RESOLUTION, NUMBER_OF_TRACKS, 2]);
+    // sources point at the first context bar
+    let starts: Vec<usize> = sources.iter().map(|source| source.bar_index).collect();
+    assert_eq!(starts, vec![0, 1]);
+    for (pair, &start) in starts.iter().enumerate() {
+        assert_eq!(contexts[[pair, start, 0, VELOCITY]], 1.);
+        assert_eq!(contexts[[pair, RESOLUTION + start + 1, 0, VELOCITY]], 1.);
+        assert_eq!(targets[[pair, start + 2, 0, VELOCITY]], 1.);
+    }
+}
+
+#[test]
+fn silent_and_repeated_targets_are_skipped() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let bars = vec![kick_bar(0), kick_bar(1), silent_bar(), kick_bar(1), kick_bar(1), kick_bar(2)];
+    let track_grids = vec![track_grid(0, bars)];
+
+    let (_, _, sources) = process_track_pool_pairs(&track_grids, &layout, 1, false, &mut diagnostics).unwrap();
+    let starts: Vec<usize> = sources.iter().map(|source| source.bar_index).collect();
+    // the silent bar 2 is never a target
+    assert_eq!(starts, vec![0, 2, 3, 4]);
+
+    // with distinct targets, bar 4 repeats its context
+    let (_, _, sources) = process_track_pool_pairs(&track_grids, &layout, 1, true, &mut diagnostics).unwrap();
+    let starts: Vec<usize> = sources.iter().map(|source| source.bar_index).collect();
+    assert_eq!(starts, vec![0, 2, 4]);
+}
+
+#[test]
+fn pairs_need_a_context_bar() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let track_grids = vec![track_grid(0, (0..4).map(kick_bar).collect())];
+
+    assert!(process_track_pool_pairs(&track_grids, &layout, 0, false, &mut diagnostics).is_err());
+}
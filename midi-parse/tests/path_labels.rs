@@ -0,0 +1,47 @@
+use std::path::Path;
+
+use midi_parse::metadata::{encode_categorical_labels, get_glob_root, PathLabel};
+
+#[test]
+fn components_count_from_the_glob_root_or_from_the_file_name() {
+    let glob_root = get_glob_root("/corpus/midi/**/*.mid");
+    assert_eq!(glob_root, Path::new("/corpus/midi"));
+
+    let path = Path::new("/corpus/midi/rock/drummer1/take_3.mid");
+    let label = |spec: &str| PathLabel::parse(spec).unwrap().extract(path, &glob_root);
+
+    assert_eq!(label("genre=0").as_deref(), Some("rock"));
+    assert_eq!(label("drummer=-2").as_deref(), Some("drummer1"));
+    assert_eq!(label("file=-1").as_deref(), Some("take_3.mid"));
+    assert_eq!(label("deep=3"), None);
+    assert_eq!(label("before=-4"), None);
+}
+
+#[test]
+fn regexes_take_their_first_group_or_the_whole_match() {
+    let glob_root = get_glob_root("/corpus/*/*.mid");
+    let path = Path::new("/corpus/jazz/120bpm_swing.mid");
+    let label = |spec: &str| PathLabel::parse(spec).unwrap().extract(path, &glob_root);
+
+    assert_eq!(label(r"tempo=re:(\d+)bpm").as_deref(), Some("120"));
+    assert_eq!(label("feel=re:swing|straight").as_deref(), Some("swing"));
+    assert_eq!(label("meter=re:(\\d+)-(\\d+)"), None);
+}
+
+#[test]
+fn bad_specs_are_rejected() {
+    assert!(PathLabel::parse("genre").is_err());
+    assert!(PathLabel::parse("genre=folder").is_err());
+    assert!(PathLabel::parse("genre=re:(").is_err());
+}
+
+#[test]
+fn labels_are_coded_in_vocabulary_order_and_missing_ones_are_minus_one() {
+    let (rock, jazz) = ("rock".to_owned(), "jazz".to_owned());
+    let values = vec![Some(&rock), None, Some(&jazz), Some(&rock)];
+
+    let (codes, vocabulary) = encode_categorical_labels(&values);
+
+    assert_eq!(vocabulary, vec!["jazz".to_owned(), "rock".to_owned()]);
+    assert_eq!(codes.to_vec(), vec![1, -1, 0, 1]);
+}
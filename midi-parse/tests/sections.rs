@@ -0,0 +1,69 @@
+mod common;
+
+use std::collections::HashMap;
+
+use common::groove;
+use midi_parse::datatypes::{Bar, DrumTrack};
+use midi_parse::map::{NUMBER_OF_TRACKS, RESOLUTION};
+use midi_parse::provenance::BarSource;
+use midi_parse::sections::{get_bar_sections, get_row_sections, get_track_sections, segment_bars};
+
+// toms on the off beats, nothing in common with the groove
+fn toms() -> Bar {
+    let mut bar = Bar::new(RESOLUTION, NUMBER_OF_TRACKS);
+    for step in (2..RESOLUTION).step_by(4) {
+        bar.set(step, 2 + step / 4 % 2, 0.8, 0.);
+    }
+    bar
+}
+
+#[test]
+fn repeated_parts_get_the_same_kind() {
+    // verse, chorus, verse
+    let bars: Vec<Bar> = (0..24).map(|bar| if (8..16).contains(&bar) { toms() } else { groove() }).collect();
+
+    let sections = segment_bars(&bars, &[]);
+
+    let starts: Vec<(usize, usize, usize)> =
+        sections.iter().map(|section| (section.start_bar, section.length, section.kind)).collect();
+    assert_eq!(starts, vec![(0, 8, 0), (8, 8, 1), (16, 8, 0)]);
+}
+
+#[test]
+fn markers_start_sections_at_their_bar() {
+    let mut track = DrumTrack::new(vec![], (4, 2, 24, 8), 480);
+    let bar_ticks = track.get_bar_track_duration() as u32;
+    // a marker a bit after the start of bar 3, nothing changes in the bars
+    track.markers = vec![(3 * bar_ticks + 10, "chorus".to_owned())];
+    let bars: Vec<Bar> = (0..6).map(|_| groove()).collect();
+
+    let sections = get_track_sections(&track, &bars);
+
+    assert_eq!(sections.len(), 2);
+    assert_eq!((sections[1].start_bar, sections[1].length), (3, 3));
+    assert_eq!(sections[1].marker.as_deref(), Some("chorus"));
+    assert_eq!(sections[0].kind, sections[1].kind);
+}
+
+#[test]
+fn rows_get_the_section_of_their_offset_bar() {
+    let bars: Vec<Bar> = (0..12).map(|bar| if bar < 4 { groove() } else { toms() }).collect();
+    let bar_sections = get_bar_sections(&segment_bars(&bars, &[]));
+    assert_eq!(bar_sections.len(), bars.len());
+    assert_eq!((bar_sections[5].section, bar_sections[5].position, bar_sections[5].length), (1, 1, 8));
+
+    let track_sections: HashMap<usize, _> = vec![(7, bar_sections)].into_iter().collect();
+    let sources = vec![
+        BarSource { track_index: 7, bar_index: 2 },
+        BarSource { track_index: 7, bar_index: 11 },
+        // no grid for this track
+        BarSource { track_index: 3, bar_index: 0 },
+    ];
+
+    let row_sections = get_row_sections(&track_sections, &sources, 2);
+
+    assert_eq!(row_sections[0].map(|section| (section.section, section.position)), Some((1, 0)));
+    // bar 13 is past the end of the track
+    assert!(row_sections[1].is_none());
+    assert!(row_sections[2].is_none());
+}
@@ -0,0 +1,69 @@
+#![cfg(feature = "serialization")]
+
+use std::collections::BTreeMap;
+
+use midi_parse::datatypes::{Bar, Drum, DrumTrack};
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT};
+use midi_parse::serialization::{from_binary, from_json, to_binary, to_json};
+
+fn track() -> DrumTrack {
+    let events: Vec<Drum> = (0..16)
+        .map(|step| Drum {
+            time: step * 120,
+            velocity: 40 + step as u8 * 5,
+            key: if step % 4 == 0 { 36 } else { 42 },
+        })
+        .collect();
+
+    let mut track = DrumTrack::new(events, (3, 4, 24, 8), 480);
+    track.tempo = 600_000;
+    track.metadata = vec![("style".to_owned(), "jazz".to_owned())]
+        .into_iter()
+        .collect::<BTreeMap<String, String>>();
+    track
+}
+
+#[test]
+fn drum_track_json_round_trip() {
+    let track = track();
+    let decoded: DrumTrack = from_json(&to_json(&track).unwrap()).unwrap();
+    assert_eq!(decoded, track);
+}
+
+#[test]
+fn drum_track_binary_round_trip() {
+    let track = track();
+    let decoded: DrumTrack = from_binary(&to_binary(&track).unwrap()).unwrap();
+    assert_eq!(decoded, track);
+}
+
+#[test]
+fn bars_round_trip() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let track = track();
+    let bars = track
+        .to_grid(&track.get_track_perc_map_with(&layout.perc_map, &layout.alt_map))
+        .unwrap();
+
+    let from_json: Vec<Bar> = from_json(&to_json(&bars).unwrap()).unwrap();
+    let from_binary: Vec<Bar> = from_binary(&to_binary(&bars).unwrap()).unwrap();
+
+    for decoded in [from_json, from_binary].iter() {
+        assert_eq!(decoded.len(), bars.len());
+        for (decoded_bar, bar) in decoded.iter().zip(bars.iter()) {
+            // Bar equality is quantized, compare the raw values
+            assert_eq!(decoded_bar.view(), bar.view());
+        }
+    }
+}
+
+#[test]
+fn layout_round_trip() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let decoded: midi_parse::map::Layout = from_binary(&to_binary(&layout).unwrap()).unwrap();
+
+    assert_eq!(decoded.name, layout.name);
+    assert_eq!(decoded.lane_names, layout.lane_names);
+    assert_eq!(decoded.perc_map, layout.perc_map);
+    assert_eq!(decoded.alt_map, layout.alt_map);
+}
@@ -0,0 +1,86 @@
+use midi_parse::split::{assign_splits, assign_splits_balanced, SplitRatios};
+
+fn counts(split_ids: &[u8]) -> [usize; 3] {
+    let mut counts = [0; 3];
+    split_ids.iter().for_each(|&split| counts[split as usize] += 1);
+    counts
+}
+
+fn files(range: std::ops::Range<usize>) -> Vec<String> {
+    range.map(|file| format!("corpus/file_{}.mid", file)).collect()
+}
+
+#[test]
+fn groups_keep_their_split_when_the_corpus_grows() {
+    let ratios = SplitRatios::parse("0.8,0.1,0.1").unwrap();
+    let before = assign_splits(&files(0..500), &ratios, 5);
+    let after = assign_splits(&files(0..2000), &ratios, 5);
+
+    assert_eq!(before[..], after[..500]);
+
+    // shares follow the ratios over many groups
+    let counts = counts(&after);
+    for (count, ratio) in counts.iter().zip([0.8, 0.1, 0.1].iter()) {
+        assert!((*count as f32 / 2000. - ratio).abs() < 0.03, "{:?}", counts);
+    }
+}
+
+#[test]
+fn rows_of_a_group_share_its_split() {
+    let groups: Vec<String> = (0..300).map(|row| format!("folder_{}", row % 7)).collect();
+    let ratios = SplitRatios::parse("0.6,0.2,0.2").unwrap();
+
+    let split_ids = assign_splits(&groups, &ratios, 1);
+
+    for (row, split) in split_ids.iter().enumerate() {
+        assert_eq!(*split, split_ids[row % 7]);
+    }
+}
+
+#[test]
+fn balanced_groups_never_cross_splits_and_small_splits_get_rows() {
+    // one big group and a few small ones, the big one alone overshoots val or test
+    let groups: Vec<String> = std::iter::repeat_n("big".to_owned(), 60)
+        .chain((0..8).flat_map(|group| std::iter::repeat_n(format!("small_{}", group), 5)))
+        .collect();
+    let ratios = SplitRatios::parse("0.8,0.1,0.1").unwrap();
+
+    for seed in 0..20 {
+        let split_ids = assign_splits_balanced(&groups, &ratios, seed);
+        assert_eq!(split_ids.len(), groups.len());
+
+        for (group, split) in groups.iter().zip(split_ids.iter()) {
+            let first = groups.iter().position(|other| other == group).unwrap();
+            assert_eq!(*split, split_ids[first]);
+        }
+
+        let counts = counts(&split_ids);
+        assert!(counts.iter().all(|&count| count > 0), "seed {}: {:?}", seed, counts);
+        // the big group always lands in train, the only split it fits in
+        assert_eq!(split_ids[0], 0, "seed {}", seed);
+    }
+}
+
+#[test]
+fn same_seed_same_splits() {
+    let groups: Vec<String> = (0..50).map(|row| format!("file_{}", row / 3)).collect();
+    let ratios = SplitRatios::parse("8,1,1").unwrap();
+
+    assert_eq!(assign_splits(&groups, &ratios, 7), assign_splits(&groups, &ratios, 7));
+    assert_ne!(assign_splits(&groups, &ratios, 7), assign_splits(&groups, &ratios, 8));
+    assert_eq!(counts(&assign_splits(&groups, &ratios, 7)).iter().sum::<usize>(), 50);
+}
+
+#[test]
+fn splits_without_a_share_stay_empty() {
+    let groups: Vec<String> = (0..20).map(|row| format!("file_{}", row)).collect();
+    let ratios = SplitRatios::parse("0.5,0.5,0").unwrap();
+
+    assert_eq!(counts(&assign_splits_balanced(&groups, &ratios, 3)), [10, 10, 0]);
+    for ratios in ["0.5,0.5,0", "0.5,0,0.5", "0,0,1"].iter() {
+        let ratios = SplitRatios::parse(ratios).unwrap();
+        let shares = [ratios.train, ratios.val, ratios.test];
+        let counts = counts(&assign_splits(&files(0..200), &ratios, 3));
+        assert!((0..3).all(|split| (counts[split] > 0) == (shares[split] > 0.)), "{:?}", counts);
+    }
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::{kick_bar, silent_bar, track_grid};
+use midi_parse::datatypes::VELOCITY;
+use midi_parse::diagnostics::Diagnostics;
+use midi_parse::map::{get_layout, process_track_pool_windows, DEFAULT_LAYOUT, NUMBER_OF_TRACKS, RESOLUTION};
+
+#[test]
+fn windows_start_every_hop_bars_and_stay_within_their_track() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    // 7 bars: windows of 2 bars every 2 bars start on bars 0, 2 and 4, bar 6 has no next bar
+    let track_grids = vec![
+        track_grid(0, (0..7).map(kick_bar).collect()),
+        // shorter than a window
+        track_grid(1, vec![kick_bar(10)]),
+    ];
+
+    let (windows, sources) =
+        process_track_pool_windows(&track_grids, &layout, RESOLUTION, 2, 2, &mut diagnostics).unwrap();
+
+    assert_eq!(windows.shape(), &[3, 2 * RESOLUTION, NUMBER_OF_TRACKS, 2]);
+    let starts: Vec<(usize, usize)> = sources.iter().map(|source| (source.track_index, source.bar_index)).collect();
+    assert_eq!(starts, vec![(0, 0), (0, 2), (0, 4)]);
+    // the second bar of each window follows the first one
+    for (window, &start) in [0, 2, 4].iter().enumerate() {
+        assert_eq!(windows[[window, start, 0, VELOCITY]], 1.);
+        assert_eq!(windows[[window, RESOLUTION + start + 1, 0, VELOCITY]], 1.);
+    }
+}
+
+#[test]
+fn silent_and_repeated_windows_are_dropped() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let bars = vec![kick_bar(0), silent_bar(), silent_bar(), kick_bar(0), silent_bar()];
+    let track_grids = vec![track_grid(0, bars)];
+
+    let (windows, sources) =
+        process_track_pool_windows(&track_grids, &layout, RESOLUTION, 2, 1, &mut diagnostics).unwrap();
+
+    // (kick, silent), (silent, silent), (silent, kick), (kick, silent) again
+    assert_eq!(windows.shape()[0], 2);
+    let starts: Vec<usize> = sources.iter().map(|source| source.bar_index).collect();
+    assert_eq!(starts, vec![0, 2]);
+}
+
+#[test]
+fn windows_need_a_hop() {
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    let mut diagnostics = Diagnostics::new();
+    let track_grids = vec![track_grid(0, (0..4).map(kick_bar).collect())];
+
+    assert!(process_track_pool_windows(&track_grids, &layout, RESOLUTION, 2, 0, &mut diagnostics).is_err());
+}
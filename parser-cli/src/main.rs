@@ -1,18 +1,50 @@
 use glob::glob_with;
 use glob::MatchOptions;
-use midly::Smf;
-use ndarray_npy::NpzWriter;
-use std::collections::BTreeMap;
+use ndarray_npy::NpzReader;
+use std::collections::{BTreeMap, HashMap};
 use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::{fs, time::Instant};
 use structopt::StructOpt;
 
-use midi_parse::datatypes::DrumTrack;
-use midi_parse::map::process_track_pool;
-use midi_parse::parse::filter_beat;
-use midi_parse::stats::{display_stats, fill_stats, filter_densities, filter_gridicity};
+mod output;
+use output::{write_shard, Shard, FORMATS};
 
-use ndarray::s;
+use midi_parse::augment::{augment_rows, parse_augmentations, Augmentation, Rng};
+use midi_parse::cache::ParseCache;
+use midi_parse::datatypes::{Bar, DrumTrack};
+use midi_parse::diagnostics::Diagnostics;
+use midi_parse::error::MidiBeatError;
+use midi_parse::explain::{explain_tracks, FileExplanation};
+use midi_parse::export::{write_midi, ExportSettings};
+use midi_parse::fidelity::{measure_tracks, FileFidelity, Fidelity, FidelityReport};
+use midi_parse::filter::{read_filter_specs, FilterPipeline};
+use midi_parse::fill::{
+    get_fill_labels, get_row_fill_scores, get_track_pool_fill_scores, FILL_LABEL, GROOVE_LABEL,
+};
+use midi_parse::gmd::{check_gmd_layout, process_track_pool_gmd, to_gmd_tensors, GMD_RESOLUTION, GMD_WINDOW_BARS};
+use midi_parse::learn::KeyStatsCollector;
+use midi_parse::manifest::{Manifest, ManifestInput, ManifestShard, MANIFEST_VERSION};
+use midi_parse::map::{
+    get_track_pool_grids, load_layout, process_track_pool_pairs, process_track_pool_windows,
+    process_track_pool_with_sources,
+    Layout, TrackGrid, DEFAULT_LAYOUT, RESOLUTION, WINDOW_BARS,
+};
+use midi_parse::metadata::{
+    encode_categorical_labels, encode_labels, get_glob_root, DatasetMetadata, LabelArray,
+    PathLabel,
+};
+use midi_parse::parse::{parse_bytes, parse_file};
+use midi_parse::provenance::{
+    get_provenance, provenance_to_jsonl, to_provenance_arrays, BarSource, Provenance, ProvenanceArrays, TrackOrigin,
+};
+use midi_parse::sections::{get_row_sections, get_track_pool_sections, BarSection};
+use midi_parse::serialization::{to_json, to_json_pretty};
+use midi_parse::split::{assign_splits, assign_splits_balanced, SplitRatios, SPLIT_NAMES, SPLIT_TOLERANCE};
+use midi_parse::stats::{display_stats, fill_stats};
+use midi_parse::utils::{content_hash, write_tracks};
+
+use ndarray::{s, stack, Array, Axis, Ix1, Ix3, Ix4};
 
 // parse args in a clean struct
 #[derive(Debug, StructOpt)]
@@ -27,29 +59,541 @@ struct Opt {
     /// Output path
     #[structopt(short, long)]
     output: String,
+    /// Lane layout preset (default, extended, gmd9, td11, minimal3) or layout CSV path
+    #[structopt(short, long, default_value = DEFAULT_LAYOUT)]
+    layout: String,
+    /// Export Groove MIDI Dataset tensors (hits, velocities, offsets, 16 steps per bar)
+    #[structopt(long)]
+    gmd: bool,
+    /// Number of bars per GMD window (2 or 4)
+    #[structopt(long, default_value = "2")]
+    gmd_bars: usize,
+    /// Number of consecutive bars per row (1, 2, 4 or 8), windows never cross track boundaries
+    #[structopt(long, default_value = "1")]
+    window_bars: usize,
+    /// Number of bars between the starts of two windows, needs --window-bars 2 or more or --gmd
+    #[structopt(long, default_value = "1")]
+    window_hop: usize,
+    /// Write (context, target) pairs: the given number of bars and the next bar of the same track
+    #[structopt(long)]
+    pairs_context: Option<usize>,
+    /// Only keep pairs whose target differs from every context bar
+    #[structopt(long)]
+    distinct_target: bool,
+    /// Keep all rows, only grooves or only fills (fill_score and fill_label are always written)
+    #[structopt(long, default_value = "all", possible_values = &["all", "grooves", "fills"])]
+    keep: String,
+    /// Row filters applied in order, like density=0.003:0.3, gridicity=0.19:0.9, lanes=kick,snare,
+    /// meter=4/4,4/2 or tempo=90:140, can be repeated. Only the density filter runs by default
+    #[structopt(long, number_of_values = 1)]
+    filter: Vec<String>,
+    /// File of row filters, one per line, applied before the --filter ones
+    #[structopt(long)]
+    filter_config: Option<String>,
+    /// Label derived from the file paths, name=index (path component from the glob root,
+    /// negative from the file name) or name=re:regex (first capture group), can be repeated
+    #[structopt(long, number_of_values = 1)]
+    path_label: Vec<String>,
+    /// Train, val and test ratios, like 0.8,0.1,0.1, rows of a same group never cross splits
+    #[structopt(long)]
+    split: Option<String>,
+    /// Split groups: file, folder or the name of a --path-label
+    #[structopt(long, default_value = "file")]
+    split_by: String,
+    /// Seed of the group hashing
+    #[structopt(long, default_value = "0")]
+    split_seed: u64,
+    /// Fill the splits largest group first, closer to the ratios but groups move when files are added or removed
+    #[structopt(long)]
+    split_balanced: bool,
+    /// Write {output}_train, {output}_val and {output}_test shards instead of a split index array
+    #[structopt(long)]
+    split_files: bool,
+    /// Augmentations applied in order to copies of the rows, comma separated, like
+    /// velocity_scale=0.7:1.2,timing_jitter=0.1,swap=closed_hh:ride, can be repeated
+    #[structopt(long, number_of_values = 1)]
+    augment: Vec<String>,
+    /// Number of augmented copies of each row per --augment
+    #[structopt(long, default_value = "1")]
+    augment_copies: usize,
+    /// Seed of the augmentations
+    #[structopt(long, default_value = "0")]
+    augment_seed: u64,
+    /// Also augment val and test rows (only train rows are augmented when splitting)
+    #[structopt(long)]
+    augment_all_splits: bool,
+    /// Dataset info CSV joined to each parsed file, columns are written as per bar labels
+    #[structopt(long)]
+    metadata: Option<String>,
+    /// Column of the metadata CSV holding the MIDI paths
+    #[structopt(long, default_value = "midi_filename")]
+    metadata_path_column: String,
+    /// Output format: compressed npz, npy (a directory of uncompressed, memory mappable .npy per shard),
+    /// safetensors or parquet (one row per dataset row)
+    #[structopt(long, default_value = "npz", possible_values = &FORMATS)]
+    format: String,
+    /// Maximum number of rows per shard
+    #[structopt(long, default_value = "2000000")]
+    shard_rows: usize,
+    /// Maximum uncompressed size in bytes of the arrays of a shard, instead of --shard-rows
+    #[structopt(long)]
+    shard_bytes: Option<u64>,
+    /// Also write the provenance of each row as a JSONL file next to each NPZ shard
+    #[structopt(long)]
+    provenance_jsonl: bool,
+    /// Directory of the parse and grid cache, files whose content didn't change since a previous
+    /// build (or an interrupted one) aren't parsed or gridded again
+    #[structopt(long)]
+    cache: Option<String>,
+    /// Debug: write the drum tracks kept from each file as MIDI to this directory, mirroring the
+    /// input folders
+    #[structopt(long)]
+    dump_tracks: Option<String>,
+    /// Diagnostics output: quiet, summary or verbose. Fallback lanes, dropped keys and rejected tracks
+    /// are only collected in verbose mode or with --diagnostics-json
+    #[structopt(long, default_value = "summary", possible_values = &["quiet", "summary", "verbose"])]
+    diagnostics: String,
+    /// Write every collected diagnostic as JSON to this path
+    #[structopt(long)]
+    diagnostics_json: Option<String>,
+    #[structopt(subcommand)]
+    cmd: Option<Command>,
 }
 
-fn main() {
-    // read options
-    let opt = Opt::from_args();
+#[derive(Debug, StructOpt)]
+enum Command {
+    /// Learn a perc map from key co-occurrence and rhythmic roles, written as a layout CSV to the output path
+    LearnMap {
+        /// Number of lanes
+        #[structopt(long, default_value = "8")]
+        lanes: usize,
+        /// Minimum number of events for a key to be mapped
+        #[structopt(long, default_value = "100")]
+        min_events: u64,
+    },
+    /// Explain how the tracks of the input files are mapped to lanes, written as JSON to the output path
+    Explain,
+    /// Export rows of an NPZ shard (input path) to a folder of MIDI files (output path), one file per row
+    Export {
+        /// Array to export: x, context or target (velocities and offsets are used with --gmd)
+        #[structopt(long, default_value = "x")]
+        array: String,
+        /// First row to export
+        #[structopt(long, default_value = "0")]
+        start: usize,
+        /// Number of rows to export, all the following rows by default
+        #[structopt(long)]
+        count: Option<usize>,
+        /// Ticks per quarter note
+        #[structopt(long, default_value = "480")]
+        ppqn: u16,
+        /// Tempo in beats per minute
+        #[structopt(long, default_value = "120")]
+        bpm: f32,
+        /// Note length in ticks
+        #[structopt(long, default_value = "60")]
+        note_length: u32,
+        /// Key of each lane, comma separated, the first key of each layout lane by default
+        #[structopt(long)]
+        keys: Option<String>,
+    },
+    /// Measure what a MIDI -> grid -> MIDI round trip loses for each input file and for the whole
+    /// corpus, written as JSON to the output path
+    Fidelity {
+        /// Steps per bar of the grid, 32 by default (16 with --gmd)
+        #[structopt(long)]
+        resolution: Option<usize>,
+    },
+}
+
+fn get_paths(input: &str) -> Vec<PathBuf> {
     let options = MatchOptions {
         case_sensitive: false,
         require_literal_separator: false,
         require_literal_leading_dot: false,
     };
 
-    // task time elapsed
-    let start = Instant::now();
+    match glob_with(input, options) {
+        Ok(paths) => paths
+            .filter_map(|path| match path {
+                Ok(path) => Some(path),
+                Err(e) => {
+                    println!("Path error: {}", e);
+                    None
+                }
+            })
+            .collect(),
+        Err(e) => {
+            println!("Pattern error: {}", e);
+            vec![]
+        }
+    }
+}
+
+fn learn_map(opt: &Opt, lanes: usize, min_events: u64) {
+    let mut collector = KeyStatsCollector::new();
+
+    println!("Reading files in : {}", opt.input);
+
+    for path in get_paths(&opt.input) {
+        match parse_file(path.as_path(), opt.drum_channel) {
+            Ok(tracks) => tracks.iter().for_each(|track| collector.add_track(track)),
+            Err(e) => println!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    let name = PathBuf::from(&opt.output)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| "learned".to_owned());
+    let layout = collector.learn_layout(&name, lanes, min_events);
+
+    layout.lane_names.iter().zip(layout.perc_map.iter()).for_each(|(lane_name, keys)| {
+        println!("{}: {:?}", lane_name, keys);
+    });
+
+    match layout.to_csv(&opt.output) {
+        Ok(_) => println!("Successfully generated layout CSV for path: '{}'", opt.output),
+        Err(e) => println!("Layout write error: {}", e),
+    }
+}
+
+fn explain(opt: &Opt, layout: &Layout) {
+    let mut reports: Vec<FileExplanation> = vec![];
+    let (resolution, _, _) = get_row_spec(opt);
+
+    for path in get_paths(&opt.input) {
+        match parse_file(path.as_path(), opt.drum_channel) {
+            Ok(tracks) => {
+                let explanations = explain_tracks(&tracks, layout, resolution);
+
+                println!("=============== {}", path.display());
+                explanations.iter().for_each(|explanation| println!("{}", explanation));
+
+                reports.push(FileExplanation {
+                    path: path.to_string_lossy().to_string(),
+                    tracks: explanations,
+                });
+            }
+            Err(e) => println!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    match to_json(&reports).and_then(|json| Ok(fs::write(&opt.output, json)?)) {
+        Ok(_) => println!("Successfully generated JSON report for path: '{}'", opt.output),
+        Err(e) => println!("Report write error: {}", e),
+    }
+}
+
+fn read_grids(opt: &Opt, array: &str) -> Result<Array<f32, Ix4>, String> {
+    let file = File::open(&opt.input).map_err(|e| e.to_string())?;
+    let mut npz = NpzReader::new(file).map_err(|e| e.to_string())?;
+
+    if opt.gmd {
+        let velocities: Array<f32, Ix3> = npz.by_name("velocities").map_err(|e| e.to_string())?;
+        let offsets: Array<f32, Ix3> = npz.by_name("offsets").map_err(|e| e.to_string())?;
+        stack(Axis(3), &[velocities.view(), offsets.view()]).map_err(|e| e.to_string())
+    } else {
+        npz.by_name(array).map_err(|e| e.to_string())
+    }
+}
+
+fn export(opt: &Opt, layout: &Layout) {
+    let (array, start, count, ppqn, bpm, note_length, keys) = match &opt.cmd {
+        Some(Command::Export {
+            array,
+            start,
+            count,
+            ppqn,
+            bpm,
+            note_length,
+            keys,
+        }) => (array, *start, *count, *ppqn, *bpm, *note_length, keys),
+        _ => return,
+    };
+
+    let mut settings = ExportSettings::new(layout);
+    settings.ppqn = ppqn;
+    settings.tempo = (60_000_000. / bpm.max(1.)).round() as u32;
+    settings.note_length = note_length;
+    settings.resolution = if opt.gmd { GMD_RESOLUTION } else { RESOLUTION };
+    if let Some(keys) = keys {
+        match keys.split(',').map(|key| key.trim().parse::<u8>()).collect::<Result<Vec<u8>, _>>() {
+            Ok(keys) => settings.keys = keys,
+            Err(e) => {
+                println!("Keys error: {}", e);
+                return;
+            }
+        }
+    }
+
+    let grids = match read_grids(opt, array) {
+        Ok(grids) => grids,
+        Err(e) => {
+            println!("NPZ read error: {}", e);
+            return;
+        }
+    };
+    let rows = grids.shape()[0];
+    let end = count.map(|count| (start + count).min(rows)).unwrap_or(rows);
+    println!("Exporting rows {}..{} of {} rows, shape: {:?}", start, end, rows, grids.shape());
+
+    if let Err(e) = fs::create_dir_all(&opt.output) {
+        println!("Output folder error: {}", e);
+        return;
+    }
+
+    let mut exported: usize = 0;
+    for row in start..end {
+        let path = Path::new(&opt.output).join(format!("{:06}.mid", row));
+        let result = Bar::from_view(grids.index_axis(Axis(0), row))
+            .and_then(|bar| write_midi(&path, &[bar], &settings));
+        match result {
+            Ok(_) => exported += 1,
+            Err(e) => println!("Export error for row {}: {}", row, e),
+        }
+    }
+
+    println!("Successfully exported {} MIDI files to: '{}'", exported, opt.output);
+}
+
+fn fidelity(opt: &Opt, layout: &Layout, resolution: Option<usize>) {
+    let resolution = resolution.unwrap_or(if opt.gmd { GMD_RESOLUTION } else { RESOLUTION });
+    let mut corpus = Fidelity::new(layout.number_of_lanes());
+    let mut files: Vec<FileFidelity> = vec![];
+
+    println!("Layout: {} ({} lanes), resolution: {}", layout.name, layout.number_of_lanes(), resolution);
+    println!("Reading files in : {}", opt.input);
+
+    for path in get_paths(&opt.input) {
+        match parse_file(path.as_path(), opt.drum_channel).and_then(|tracks| measure_tracks(&tracks, layout, resolution)) {
+            Ok(file) => {
+                println!("{}: {}", path.display(), file);
+                files.push(FileFidelity {
+                    path: path.to_string_lossy().to_string(),
+                    fidelity: file.summary(&layout.lane_names),
+                });
+                corpus.add(&file);
+            }
+            Err(e) => println!("Skipping {}: {}", path.display(), e),
+        }
+    }
+
+    let report = FidelityReport {
+        layout: layout.name.clone(),
+        resolution,
+        corpus: corpus.summary(&layout.lane_names),
+        files,
+    };
+
+    println!("=============== corpus");
+    println!("{}", corpus);
+    report.corpus.lanes.iter().for_each(|lane| {
+        println!(
+            "{}: {} events, {} kept, timing error {:.2} ticks, velocity error {:.2}",
+            lane.name, lane.events, lane.kept, lane.mean_timing_error_ticks, lane.mean_velocity_error
+        );
+    });
+
+    match to_json(&report).and_then(|json| Ok(fs::write(&opt.output, json)?)) {
+        Ok(_) => println!("Successfully generated JSON report for path: '{}'", opt.output),
+        Err(e) => println!("Report write error: {}", e),
+    }
+}
+
+// options a dataset was built with, for its manifest
+fn get_build_config(opt: &Opt) -> BTreeMap<String, String> {
+    let optional = |value: &Option<String>| value.clone().unwrap_or_default();
+    vec![
+        ("input", opt.input.clone()),
+        ("layout", opt.layout.clone()),
+        ("drum_channel", opt.drum_channel.to_string()),
+        ("gmd", opt.gmd.to_string()),
+        ("gmd_bars", opt.gmd_bars.to_string()),
+        ("window_bars", opt.window_bars.to_string()),
+        ("window_hop", opt.window_hop.to_string()),
+        ("pairs_context", opt.pairs_context.map(|bars| bars.to_string()).unwrap_or_default()),
+        ("distinct_target", opt.distinct_target.to_string()),
+        ("keep", opt.keep.clone()),
+        ("filter", opt.filter.join(" ")),
+        ("filter_config", optional(&opt.filter_config)),
+        ("path_label", opt.path_label.join(" ")),
+        ("split", optional(&opt.split)),
+        ("split_by", opt.split_by.clone()),
+        ("split_seed", opt.split_seed.to_string()),
+        ("split_balanced", opt.split_balanced.to_string()),
+        ("augment", opt.augment.join(" ")),
+        ("augment_copies", opt.augment_copies.to_string()),
+        ("augment_seed", opt.augment_seed.to_string()),
+        ("augment_all_splits", opt.augment_all_splits.to_string()),
+        ("metadata", optional(&opt.metadata)),
+        ("shard_rows", opt.shard_rows.to_string()),
+        ("shard_bytes", opt.shard_bytes.map(|bytes| bytes.to_string()).unwrap_or_default()),
+    ]
+    .into_iter()
+    .map(|(key, value)| (key.to_owned(), value))
+    .collect()
+}
+
+// options checked and parsed before reading any file
+struct BuildConfig {
+    path_labels: Vec<PathLabel>,
+    glob_root: PathBuf,
+    split_ratios: Option<SplitRatios>,
+    augmentation_chains: Vec<Vec<Augmentation>>,
+    filters: FilterPipeline,
+    metadata: Option<DatasetMetadata>,
+}
+
+// tracks of the input files, with the file each track comes from
+struct TrackPool {
+    tracks: Vec<DrumTrack>,
+    origins: Vec<TrackOrigin>,
+    // content hash of each input file, for the manifest
+    inputs: Vec<ManifestInput>,
+}
+
+// rows cut from the grids of the pool, with their bar sources and fill scores
+struct Rows {
+    array: Array<f32, Ix4>,
+    // targets are only there for pairs, array then holds the contexts
+    targets: Option<Array<f32, Ix4>>,
+    sources: Vec<BarSource>,
+    fill_scores: Array<f32, Ix1>,
+    fill_labels: Array<i64, Ix1>,
+}
+
+// rows written to the output, in split order with their augmented copies
+struct Dataset {
+    rows: Rows,
+    split_ids: Option<Array<u8, Ix1>>,
+    // 0 for originals and 1 + index of the --augment for copies
+    augmentation_tags: Vec<usize>,
+}
+
+// per row labels written next to the grids
+struct RowLabels {
+    sections: Vec<(&'static str, Array<i64, Ix1>)>,
+    section_marker_codes: Array<i64, Ix1>,
+    section_marker_vocabulary: Vec<String>,
+    provenance: Vec<Provenance>,
+    provenance_arrays: ProvenanceArrays,
+    // (name, codes, vocabulary) of each --path-label
+    path_labels: Vec<(String, Array<i64, Ix1>, Vec<String>)>,
+    metadata: Vec<(String, LabelArray)>,
+}
+
+fn get_config(opt: &Opt, layout: &Layout) -> Result<BuildConfig, String> {
+    if opt.gmd && !GMD_WINDOW_BARS.contains(&opt.gmd_bars) {
+        return Err(format!("GMD windows can only be {:?} bars long", GMD_WINDOW_BARS));
+    }
+
+    if opt.gmd {
+        check_gmd_layout(layout).map_err(|e| format!("Layout error: {}", e))?;
+    }
+
+    if !WINDOW_BARS.contains(&opt.window_bars) {
+        return Err(format!("Windows can only be {:?} bars long", WINDOW_BARS));
+    }
+
+    if opt.window_hop == 0 {
+        return Err("Window hop must be at least 1 bar".to_owned());
+    }
+
+    // single bars and pairs take every bar
+    if opt.window_hop > 1 && !opt.gmd && (opt.window_bars == 1 || opt.pairs_context.is_some()) {
+        return Err("Window hop needs --window-bars 2 or more, or --gmd".to_owned());
+    }
+
+    if opt.pairs_context.is_some() && (opt.gmd || opt.window_bars > 1) {
+        return Err("Pairs can't be combined with GMD or multi-bar windows".to_owned());
+    }
+
+    let path_labels: Vec<PathLabel> = opt
+        .path_label
+        .iter()
+        .map(|spec| PathLabel::parse(spec))
+        .collect::<Result<Vec<PathLabel>, MidiBeatError>>()
+        .map_err(|e| format!("Path label error: {}", e))?;
+
+    let split_ratios = match &opt.split {
+        Some(spec) => Some(SplitRatios::parse(spec).map_err(|e| format!("Split error: {}", e))?),
+        None => None,
+    };
+    let is_path_label = path_labels.iter().any(|path_label| path_label.name == opt.split_by);
+    if split_ratios.is_some() && !["file", "folder"].contains(&opt.split_by.as_str()) && !is_path_label {
+        return Err(format!(
+            "Split groups should be file, folder or a path label name, got {}",
+            opt.split_by
+        ));
+    }
+
+    let augmentation_chains: Vec<Vec<Augmentation>> = opt
+        .augment
+        .iter()
+        .map(|spec| parse_augmentations(spec, layout))
+        .collect::<Result<Vec<Vec<Augmentation>>, MidiBeatError>>()
+        .map_err(|e| format!("Augmentation error: {}", e))?;
+
+    let filter_specs: Vec<String> = match &opt.filter_config {
+        Some(path) => read_filter_specs(path).map_err(|e| format!("Filter config error: {}", e))?,
+        None => vec![],
+    }
+    .into_iter()
+    .chain(opt.filter.iter().cloned())
+    .collect();
+    let steps_per_bar = if opt.gmd { GMD_RESOLUTION } else { RESOLUTION };
+    let filters = if filter_specs.is_empty() {
+        FilterPipeline::default_filters(steps_per_bar)
+    } else {
+        FilterPipeline::parse(&filter_specs, layout, steps_per_bar).map_err(|e| format!("Filter error: {}", e))?
+    };
+    println!("Filters: {}", filters);
+
+    let metadata = match &opt.metadata {
+        Some(csv_path) => {
+            let metadata = DatasetMetadata::from_csv(csv_path, &opt.metadata_path_column)
+                .map_err(|e| format!("Metadata error: {}", e))?;
+            println!("Metadata: {} rows, columns {:?}", metadata.len(), metadata.columns);
+            Some(metadata)
+        }
+        None => None,
+    };
+
+    Ok(BuildConfig {
+        path_labels,
+        glob_root: get_glob_root(&opt.input),
+        split_ratios,
+        augmentation_chains,
+        filters,
+        metadata,
+    })
+}
+
+// parses every input file into the track pool, with the key and time signature stats
+fn load(
+    opt: &Opt,
+    layout: &Layout,
+    config: &BuildConfig,
+    mut cache: Option<&mut ParseCache>,
+    diagnostics: &mut Diagnostics,
+) -> Result<TrackPool, String> {
     let mut counter: u32 = 0;
+    let mut unmatched_files: u32 = 0;
+    let (resolution, _, _) = get_row_spec(opt);
 
     // for stats
     let mut key_map: BTreeMap<u8, u64> = BTreeMap::new();
     let mut ts_map: BTreeMap<(u8, u8, u8, u8), u64> = BTreeMap::new();
+    let mut unmapped_key_map: BTreeMap<u8, u64> = BTreeMap::new();
     let count: u64 = 1;
     let ts_count: u64 = 1;
 
-    // track pool
-    let mut track_pool: Vec<DrumTrack> = Vec::new();
+    let mut pool = TrackPool {
+        tracks: Vec::new(),
+        origins: Vec::new(),
+        inputs: Vec::new(),
+    };
 
     if opt.drum_channel {
         println!("Filter on Channel 10 only ....");
@@ -57,79 +601,685 @@ fn main() {
         println!("No Channel filtering");
     }
 
+    println!("Layout: {} ({} lanes)", layout.name, layout.number_of_lanes());
     println!("Reading files in : {}", opt.input);
 
-    match glob_with(&opt.input, options) {
-        Ok(paths) => {
-            paths.into_iter().for_each(|path| {
-                match path {
-                    Ok(path) => {
-                        // println!("Parsing file: {}", path.display());
-                        let data = fs::read(path.as_path()).unwrap();
-
-                        // Parse the raw bytes
-                        match Smf::parse(&data) {
-                            Ok(smf) => {
-                                let mut tracks = filter_beat(smf, opt.drum_channel);
-                                fill_stats(&tracks, count, &mut key_map, ts_count, &mut ts_map);
-                                track_pool.append(&mut tracks);
-                            }
-                            Err(e) => {
-                                println!("SMF parsing error: {}", e);
-                                counter += 1;
-                            }
-                        }
+    get_paths(&opt.input).into_iter().for_each(|path| {
+        // println!("Parsing file: {}", path.display());
+        diagnostics.set_file(Some(path.to_string_lossy().to_string()));
+        let parsed = fs::read(&path).map_err(MidiBeatError::from).and_then(|bytes| {
+            pool.inputs.push(ManifestInput {
+                path: path.to_string_lossy().to_string(),
+                hash: content_hash(&bytes),
+            });
+            match cache.as_mut() {
+                Some(cache) => cache.parse(&bytes, opt.drum_channel, diagnostics),
+                None => parse_bytes(&bytes, opt.drum_channel),
+            }
+        });
+        match parsed {
+            Ok(mut tracks) => {
+                diagnostics.record_tracks(&tracks, layout, resolution);
+
+                if let Some(directory) = &opt.dump_tracks {
+                    let relative = path.strip_prefix(&config.glob_root).unwrap_or(&path);
+                    let dump_path = Path::new(directory).join(relative).with_extension("mid");
+                    let dumped = dump_path
+                        .parent()
+                        .map_or(Ok(()), fs::create_dir_all)
+                        .map_err(MidiBeatError::from)
+                        .and_then(|_| write_tracks(&dump_path, &tracks));
+                    if let Err(e) = dumped {
+                        diagnostics.warn("dump_failed", None, e.to_string());
                     }
-                    Err(e) => {
-                        println!("Path error: {}", e);
+                }
+
+                if let Some(metadata) = &config.metadata {
+                    match metadata.get(path.as_path()) {
+                        Some(row) => tracks
+                            .iter_mut()
+                            .for_each(|track| track.metadata = row.clone()),
+                        None => unmatched_files += 1,
                     }
                 }
-            });
+
+                fill_stats(
+                    &tracks,
+                    count,
+                    &mut key_map,
+                    ts_count,
+                    &mut ts_map,
+                    &mut unmapped_key_map,
+                    layout,
+                );
+                pool.origins.extend((0..tracks.len()).map(|track_index| TrackOrigin {
+                    path: path.to_string_lossy().to_string(),
+                    track_index,
+                }));
+                pool.tracks.append(&mut tracks);
+            }
+            Err(e) => {
+                // skip and report bad files
+                diagnostics.error("skipped_file", None, e.to_string());
+                counter += 1;
+            }
         }
-        Err(e) => {
-            println!("Pattern error: {}", e);
+    });
+    diagnostics.set_file(None);
+
+    if let Some(cache) = cache {
+        println!("Parse cache: {} files reused, {} parsed", cache.hits, cache.misses);
+    }
+
+    if opt.diagnostics != "quiet" {
+        display_stats(&key_map, &ts_map, &unmapped_key_map, counter);
+    }
+
+    if config.metadata.is_some() {
+        println!("====> {} files had no metadata row", unmatched_files);
+    }
+
+    Ok(pool)
+}
+
+// resolution of the grids, and bars of each row scored for fills and sections:
+// the target bar for pairs, every bar of windows, as (resolution, offset, span)
+fn get_row_spec(opt: &Opt) -> (usize, usize, usize) {
+    match (opt.pairs_context, opt.gmd) {
+        (Some(context_bars), _) => (RESOLUTION, context_bars, 1),
+        (None, true) => (GMD_RESOLUTION, 0, opt.gmd_bars),
+        (None, false) => (RESOLUTION, 0, opt.window_bars),
+    }
+}
+
+// every track is turned into a grid once, for the rows, the fills and the sections.
+// With a cache, the grids of each file are read from the cache or written to it
+fn grid(
+    opt: &Opt,
+    pool: &TrackPool,
+    layout: &Layout,
+    resolution: usize,
+    cache: Option<&mut ParseCache>,
+    diagnostics: &mut Diagnostics,
+) -> Vec<TrackGrid> {
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return get_track_pool_grids(&pool.tracks, layout, resolution, diagnostics),
+    };
+
+    let hashes: HashMap<&str, &str> =
+        pool.inputs.iter().map(|input| (input.path.as_str(), input.hash.as_str())).collect();
+    let mut track_grids: Vec<TrackGrid> = vec![];
+    // tracks of a file follow each other in the pool
+    let mut first_track_index = 0;
+    while first_track_index < pool.tracks.len() {
+        let path = &pool.origins[first_track_index].path;
+        let end = pool.origins[first_track_index..]
+            .iter()
+            .position(|origin| &origin.path != path)
+            .map_or(pool.tracks.len(), |length| first_track_index + length);
+        let tracks = &pool.tracks[first_track_index..end];
+
+        let file_grids = cache.grids(hashes[path.as_str()], opt.drum_channel, tracks, layout, resolution, diagnostics);
+        for track_grid in file_grids.into_iter() {
+            match track_grid {
+                Ok(track_grid) => track_grids.push(TrackGrid {
+                    track_index: first_track_index + track_grid.track_index,
+                    ..track_grid
+                }),
+                Err((track_index, e)) => {
+                    diagnostics.warn("skipped_track", Some(first_track_index + track_index), e.to_string())
+                }
+            }
+        }
+        first_track_index = end;
+    }
+
+    println!("Grid cache: {} files reused, {} gridded", cache.grid_hits, cache.grid_misses);
+    track_grids
+}
+
+// rows of the grids with their fill scores, and the indices of the rows kept by the filters
+fn rows(
+    opt: &Opt,
+    layout: &Layout,
+    config: &BuildConfig,
+    pool: &TrackPool,
+    track_grids: &[TrackGrid],
+    (offset, span): (usize, usize),
+    diagnostics: &mut Diagnostics,
+) -> Result<(Rows, Vec<usize>), MidiBeatError> {
+    let without_targets = |processed: Result<(Array<f32, Ix4>, Vec<BarSource>), MidiBeatError>| {
+        processed.map(|(array, sources)| (array, None, sources))
+    };
+    let (array, targets, sources) = if let Some(context_bars) = opt.pairs_context {
+        process_track_pool_pairs(track_grids, layout, context_bars, opt.distinct_target, diagnostics)
+            .map(|(contexts, targets, sources)| (contexts, Some(targets), sources))
+    } else if opt.gmd {
+        without_targets(process_track_pool_gmd(track_grids, layout, opt.gmd_bars, opt.window_hop, diagnostics))
+    } else if opt.window_bars > 1 {
+        without_targets(process_track_pool_windows(
+            track_grids,
+            layout,
+            RESOLUTION,
+            opt.window_bars,
+            opt.window_hop,
+            diagnostics,
+        ))
+    } else {
+        without_targets(process_track_pool_with_sources(track_grids, layout))
+    }?;
+
+    println!(
+        "Successful cast of bars vec into Array4, shape: {:?}",
+        array.shape()
+    );
+
+    // filter rows, keeping bar sources aligned
+    let row_tracks: Vec<&DrumTrack> = sources.iter().map(|source| &pool.tracks[source.track_index]).collect();
+    let kept = config.filters.kept_indices(&array, &row_tracks, diagnostics);
+
+    // fill scores of the bars each row is made of
+    let fill_scores = get_track_pool_fill_scores(track_grids, layout);
+    let fill_scores = get_row_fill_scores(&fill_scores, &sources, offset, span);
+    let fill_labels = get_fill_labels(&fill_scores);
+
+    let wanted_label = match opt.keep.as_str() {
+        "grooves" => Some(GROOVE_LABEL),
+        "fills" => Some(FILL_LABEL),
+        _ => None,
+    };
+    let filters_kept = kept.len();
+    let kept: Vec<usize> = kept
+        .into_iter()
+        .filter(|&row| wanted_label.map(|label| fill_labels[row] == label).unwrap_or(true))
+        .collect();
+    if wanted_label.is_some() {
+        diagnostics.info(
+            "fill_filter",
+            None,
+            format!("kept {}: {}, rejected: {}", opt.keep, kept.len(), filters_kept - kept.len()),
+        );
+    }
+
+    let rows = Rows {
+        array,
+        targets,
+        sources,
+        fill_scores,
+        fill_labels,
+    };
+    Ok((rows, kept))
+}
+
+// split of each kept row, rows of each split are made contiguous, train first
+fn split(
+    opt: &Opt,
+    config: &BuildConfig,
+    pool: &TrackPool,
+    rows: &Rows,
+    kept: Vec<usize>,
+    diagnostics: &mut Diagnostics,
+) -> (Vec<usize>, Option<Vec<u8>>) {
+    let ratios = match &config.split_ratios {
+        Some(ratios) => ratios,
+        None => return (kept, None),
+    };
+    let split_label = config
+        .path_labels
+        .iter()
+        .find(|path_label| path_label.name == opt.split_by);
+
+    let groups: Vec<String> = kept
+        .iter()
+        .map(|&row| {
+            let path = Path::new(&pool.origins[rows.sources[row].track_index].path);
+            match (opt.split_by.as_str(), split_label) {
+                ("folder", _) => path.parent().unwrap_or(path).to_string_lossy().to_string(),
+                // files without the label are groups of their own
+                (_, Some(path_label)) => path_label
+                    .extract(path, &config.glob_root)
+                    .unwrap_or_else(|| path.to_string_lossy().to_string()),
+                _ => path.to_string_lossy().to_string(),
+            }
+        })
+        .collect();
+    let split_ids = if opt.split_balanced {
+        assign_splits_balanced(&groups, ratios, opt.split_seed)
+    } else {
+        assign_splits(&groups, ratios, opt.split_seed)
+    };
+
+    // stable, rows keep their order within a split
+    let mut split_rows: Vec<(usize, u8)> = kept.into_iter().zip(split_ids).collect();
+    split_rows.sort_by_key(|(_, split)| *split);
+    let (kept, split_ids): (Vec<usize>, Vec<u8>) = split_rows.into_iter().unzip();
+
+    // whole groups rarely add up to the exact ratios
+    let shares = [ratios.train, ratios.val, ratios.test];
+    for (split, name) in SPLIT_NAMES.iter().enumerate() {
+        let count = split_ids.iter().filter(|&&id| id as usize == split).count();
+        let share = count as f32 / split_ids.len().max(1) as f32;
+        println!("Split {}: {} rows ({:.3}, asked {:.3})", name, count, share, shares[split]);
+
+        if shares[split] > 0. && count == 0 {
+            diagnostics.warn(
+                "empty_split",
+                None,
+                format!("no rows in {}, fewer {} groups than splits", name, opt.split_by),
+            );
+        } else if (share - shares[split]).abs() > SPLIT_TOLERANCE {
+            diagnostics.warn(
+                "split_ratio",
+                None,
+                format!("{} holds {:.3} of the rows instead of {:.3}", name, share, shares[split]),
+            );
         }
     }
+    (kept, Some(split_ids))
+}
 
-    display_stats(&key_map, &ts_map, counter);
+// selects the kept rows, augmented copies follow their original row in the same split
+fn augment(
+    opt: &Opt,
+    config: &BuildConfig,
+    rows: Rows,
+    kept: Vec<usize>,
+    split_ids: Option<Vec<u8>>,
+    resolution: usize,
+) -> Result<Dataset, MidiBeatError> {
+    let mut augmentation_tags: Vec<usize> = vec![];
+    let (kept, split_ids) = if config.augmentation_chains.is_empty() {
+        (kept, split_ids)
+    } else {
+        let mut augmented_kept: Vec<usize> = vec![];
+        let mut augmented_split_ids: Vec<u8> = vec![];
+        for (position, &row) in kept.iter().enumerate() {
+            let split = split_ids.as_ref().map(|split_ids| split_ids[position]).unwrap_or(0);
+            let copies = if split == 0 || opt.augment_all_splits { opt.augment_copies } else { 0 };
 
-    match process_track_pool(&track_pool) {
-        Ok(array) => {
-            println!(
-                "Successful cast of bars vec into Array4, shape: {:?}",
-                array.shape()
+            let tags = std::iter::once(0).chain(
+                (1..=config.augmentation_chains.len()).flat_map(|tag| std::iter::repeat_n(tag, copies)),
             );
+            for tag in tags {
+                augmented_kept.push(row);
+                augmented_split_ids.push(split);
+                augmentation_tags.push(tag);
+            }
+        }
+        println!("Augmented rows: {}", augmented_kept.len() - kept.len());
 
-            // filter densities
-            match filter_densities(&array) {
-                Ok(filtered) => {
-                    println!("Filtered shape: {:?}", filtered.shape());
-                    let size_limit = 2_000_000; // Adjust this as needed
-                    let num_chunks = (filtered.shape()[0] + size_limit - 1) / size_limit;
+        (augmented_kept, split_ids.map(|_| augmented_split_ids))
+    };
 
-                    for i in 0..num_chunks {
-                        let start = i * size_limit;
-                        let end = start + size_limit.min(filtered.shape()[0] - start);
-                        let chunk = filtered.slice(s![start..end, .., .., ..]);
+    let mut array = rows.array.select(Axis(0), &kept);
+    let mut targets = rows.targets.as_ref().map(|targets| targets.select(Axis(0), &kept));
+    if !config.augmentation_chains.is_empty() {
+        let mut rng = Rng::new(opt.augment_seed);
+        augment_rows(
+            &mut array,
+            targets.as_mut(),
+            &augmentation_tags,
+            &config.augmentation_chains,
+            resolution,
+            &mut rng,
+        )?;
+    }
 
-                        let output_path = format!("{}_{}.npz", opt.output, i);
+    Ok(Dataset {
+        rows: Rows {
+            array,
+            targets,
+            sources: kept.iter().map(|&row| rows.sources[row]).collect(),
+            fill_scores: rows.fill_scores.select(Axis(0), &kept),
+            fill_labels: rows.fill_labels.select(Axis(0), &kept),
+        },
+        split_ids: split_ids.map(Array::from),
+        augmentation_tags,
+    })
+}
 
-                        let mut npz = NpzWriter::new_compressed(
-                            File::create(&output_path).expect("Output path error"),
-                        );
+// sections, provenance, path labels and metadata labels of each row
+fn labels(
+    config: &BuildConfig,
+    layout: &Layout,
+    pool: &TrackPool,
+    track_grids: &[TrackGrid],
+    dataset: &Dataset,
+    offset: usize,
+) -> RowLabels {
+    let sources = &dataset.rows.sources;
 
-                        npz.add_array("x", &chunk).expect("Can't write our array");
+    // sections of the first bar of each row, the target bar for pairs
+    let track_sections = get_track_pool_sections(&pool.tracks, track_grids);
+    let row_sections = get_row_sections(&track_sections, sources, offset);
+    let section_field = |field: &dyn Fn(&BarSection) -> usize| -> Array<i64, Ix1> {
+        row_sections
+            .iter()
+            .map(|section| section.map(|section| field(section) as i64).unwrap_or(-1))
+            .collect()
+    };
+    let sections: Vec<(&str, Array<i64, Ix1>)> = vec![
+        ("section_id", section_field(&|section| section.section)),
+        ("section_kind", section_field(&|section| section.kind)),
+        ("section_position", section_field(&|section| section.position)),
+        ("section_length", section_field(&|section| section.length)),
+    ];
+    let section_markers: Vec<Option<&String>> = row_sections
+        .iter()
+        .map(|section| section.and_then(|section| section.marker.as_ref()))
+        .collect();
+    // markers are names even when they look like numbers ("1", "2", ...)
+    let (section_marker_codes, section_marker_vocabulary) = encode_categorical_labels(&section_markers);
 
-                        println!("Successfully generated NPZ for path: '{}'", output_path);
-                    }
+    let provenance = get_provenance(&pool.tracks, &pool.origins, sources, layout);
+    let provenance_arrays = to_provenance_arrays(&provenance);
+
+    // labels from the path of the file of each row
+    let path_labels: Vec<(String, Array<i64, Ix1>, Vec<String>)> = config
+        .path_labels
+        .iter()
+        .map(|path_label| {
+            let values: Vec<Option<String>> = provenance
+                .iter()
+                .map(|row| path_label.extract(Path::new(&row.path), &config.glob_root))
+                .collect();
+            let (codes, vocabulary) = encode_categorical_labels(&values.iter().map(|value| value.as_ref()).collect());
+            println!("Path label {}: {:?}", path_label.name, vocabulary);
+            (path_label.name.clone(), codes, vocabulary)
+        })
+        .collect();
+
+    // per bar labels from the metadata joined to each track
+    let metadata: Vec<(String, LabelArray)> = match &config.metadata {
+        Some(metadata) => metadata
+            .columns
+            .iter()
+            .map(|column| {
+                let values: Vec<Option<&String>> = sources
+                    .iter()
+                    .map(|source| pool.tracks[source.track_index].metadata.get(column))
+                    .collect();
+                (column.clone(), encode_labels(&values))
+            })
+            .collect(),
+        None => vec![],
+    };
+
+    RowLabels {
+        sections,
+        section_marker_codes,
+        section_marker_vocabulary,
+        provenance,
+        provenance_arrays,
+        path_labels,
+        metadata,
+    }
+}
+
+// writes the shards of the dataset and its manifest
+// features of the grid arrays of a written shard: GMD tensors are one feature each,
+// the last axis of x, context and target holds the velocity and offset of each step
+fn get_features(shard: &ManifestShard) -> Vec<String> {
+    shard
+        .arrays
+        .iter()
+        .flat_map(|array| match array.name.as_str() {
+            "hits" | "velocities" | "offsets" => vec![array.name.clone()],
+            "x" | "context" | "target" => ["velocity", "offset"]
+                .iter()
+                .map(|feature| format!("{}.{}", array.name, feature))
+                .collect(),
+            _ => vec![],
+        })
+        .collect()
+}
+
+fn write(
+    opt: &Opt,
+    layout: &Layout,
+    config: &BuildConfig,
+    pool: TrackPool,
+    dataset: &Dataset,
+    labels: &RowLabels,
+    resolution: usize,
+) {
+    let filtered = &dataset.rows.array;
+    println!("Filtered shape: {:?}", filtered.shape());
+    // arrays the shards are views of
+    let gmd_tensors = if opt.gmd { Some(to_gmd_tensors(filtered.view())) } else { None };
+    let augmentation_tags: Array<i64, Ix1> = dataset.augmentation_tags.iter().map(|&tag| tag as i64).collect();
+    let augmentation_vocabulary: Vec<String> =
+        std::iter::once("original".to_owned()).chain(opt.augment.iter().cloned()).collect();
+    let layout_vocabulary = vec![layout.name.clone()];
+
+    // everything written for the rows start..end
+    let build_shard = |start: usize, end: usize| -> Shard {
+        let chunk = filtered.slice(s![start..end, .., .., ..]);
+
+        let mut shard = Shard::new(end - start);
+
+        if let Some(tensors) = &gmd_tensors {
+            shard.add_f32("hits", tensors.hits.slice(s![start..end, .., ..]));
+            shard.add_f32("velocities", tensors.velocities.slice(s![start..end, .., ..]));
+            shard.add_f32("offsets", tensors.offsets.slice(s![start..end, .., ..]));
+        } else if let Some(targets) = &dataset.rows.targets {
+            shard.add_f32("context", chunk);
+            shard.add_f32("target", targets.slice(s![start..end, .., .., ..]));
+        } else {
+            shard.add_f32("x", chunk);
+        }
+
+        for (column, label_array) in labels.metadata.iter() {
+            match label_array {
+                LabelArray::Numeric(values) => {
+                    shard.add_f32(&format!("meta_{}", column), values.slice(s![start..end]));
                 }
-                Err(e) => {
-                    println!("Shape error: {}", e);
+                LabelArray::Categorical(codes, vocabulary) => {
+                    shard.add_i64(&format!("meta_{}", column), codes.slice(s![start..end]));
+                    shard.add_vocabulary(&format!("meta_{}_vocab", column), vocabulary);
+                }
+            }
+        }
+
+        shard.add_f32("fill_score", dataset.rows.fill_scores.slice(s![start..end]));
+        shard.add_i64("fill_label", dataset.rows.fill_labels.slice(s![start..end]));
+
+        for (name, codes, vocabulary) in labels.path_labels.iter() {
+            shard.add_i64(&format!("label_{}", name), codes.slice(s![start..end]));
+            shard.add_vocabulary(&format!("label_{}_vocab", name), vocabulary);
+        }
+
+        for (name, values) in labels.sections.iter() {
+            shard.add_i64(name, values.slice(s![start..end]));
+        }
+        shard.add_i64("section_marker", labels.section_marker_codes.slice(s![start..end]));
+        shard.add_vocabulary("section_marker_vocab", &labels.section_marker_vocabulary);
+
+        if !config.augmentation_chains.is_empty() {
+            shard.add_i64("augmentation", augmentation_tags.slice(s![start..end]));
+            shard.add_vocabulary("augmentation_vocab", &augmentation_vocabulary);
+        }
+
+        if let Some(split_ids) = &dataset.split_ids {
+            shard.add_u8("split", split_ids.slice(s![start..end]));
+        }
+
+        // provenance, aligned with the rows of the shard
+        let provenance_arrays = &labels.provenance_arrays;
+        shard.add_i64("prov_path", provenance_arrays.path.slice(s![start..end]));
+        shard.add_vocabulary("prov_path_vocab", &provenance_arrays.paths);
+        shard.add_i64("prov_track", provenance_arrays.track.slice(s![start..end]));
+        shard.add_i64("prov_bar", provenance_arrays.bar.slice(s![start..end]));
+        shard.add_u8("prov_time_signature", provenance_arrays.time_signature.slice(s![start..end, ..]));
+        shard.add_i64("prov_ppqn", provenance_arrays.ppqn.slice(s![start..end]));
+        shard.add_i64("prov_tempo", provenance_arrays.tempo.slice(s![start..end]));
+        shard.add_vocabulary("prov_layout", &layout_vocabulary);
+
+        shard
+    };
+
+    // rows per shard, from the uncompressed size of a row when sharding by bytes
+    let rows = filtered.shape()[0];
+    let shard_rows = match opt.shard_bytes {
+        Some(shard_bytes) if rows > 0 => ((shard_bytes / build_shard(0, 1).bytes().max(1)) as usize).max(1),
+        _ => opt.shard_rows.max(1),
+    };
+
+    // (output prefix, split, first row, end row) of each group of shards
+    let segments: Vec<(String, Option<&str>, usize, usize)> = match (&dataset.split_ids, opt.split_files) {
+        (Some(split_ids), true) => SPLIT_NAMES
+            .iter()
+            .enumerate()
+            .map(|(split, name)| {
+                let segment_start = split_ids.iter().filter(|&&id| (id as usize) < split).count();
+                let segment_end = split_ids.iter().filter(|&&id| (id as usize) <= split).count();
+                (format!("{}_{}", opt.output, name), Some(*name), segment_start, segment_end)
+            })
+            .filter(|(_, _, segment_start, segment_end)| segment_end > segment_start)
+            .collect(),
+        _ => vec![(opt.output.clone(), None, 0, rows)],
+    };
+
+    let mut manifest_shards: Vec<ManifestShard> = vec![];
+    for (prefix, split, segment_start, segment_end) in segments.into_iter() {
+        let num_chunks = (segment_end - segment_start).div_ceil(shard_rows);
+
+        for i in 0..num_chunks {
+            let start = segment_start + i * shard_rows;
+            let end = start + shard_rows.min(segment_end - start);
+            let shard = build_shard(start, end);
+
+            match write_shard(&shard, &opt.format, &format!("{}_{}", prefix, i)) {
+                Ok(output_path) => {
+                    println!("Successfully generated {} for path: '{}'", opt.format, output_path);
+                    manifest_shards.push(shard.to_manifest_shard(&output_path, split, start));
                 }
+                Err(e) => println!("Output write error: {}", e),
             }
+
+            if opt.provenance_jsonl {
+                let jsonl_path = format!("{}_{}_provenance.jsonl", prefix, i);
+                let jsonl = provenance_to_jsonl(&labels.provenance[start..end]);
+                match jsonl.and_then(|jsonl| Ok(fs::write(&jsonl_path, jsonl)?)) {
+                    Ok(_) => println!("Successfully generated provenance for path: '{}'", jsonl_path),
+                    Err(e) => println!("Provenance write error: {}", e),
+                }
+            }
+        }
+    }
+
+    let manifest = Manifest {
+        version: MANIFEST_VERSION,
+        format: opt.format.clone(),
+        rows,
+        resolution,
+        layout: layout.name.clone(),
+        lane_names: layout.lane_names.clone(),
+        features: manifest_shards.first().map(get_features).unwrap_or_default(),
+        config: get_build_config(opt),
+        inputs: pool.inputs,
+        shards: manifest_shards,
+    };
+    let manifest_path = format!("{}_manifest.json", opt.output);
+    match to_json_pretty(&manifest).and_then(|json| Ok(fs::write(&manifest_path, json)?)) {
+        Ok(_) => println!("Successfully generated manifest for path: '{}'", manifest_path),
+        Err(e) => println!("Manifest write error: {}", e),
+    }
+}
+
+fn main() {
+    // read options
+    let opt = Opt::from_args();
+
+    if let Some(Command::LearnMap { lanes, min_events }) = opt.cmd {
+        return learn_map(&opt, lanes, min_events);
+    }
+
+    let mut layout = match load_layout(&opt.layout) {
+        Ok(layout) => layout,
+        Err(e) => {
+            println!("Layout error: {}", e);
+            return;
+        }
+    };
+
+    // GrooVAE tensors play every key of a class on its lane, whatever the layout
+    if opt.gmd {
+        layout.merge_keys = true;
+    }
+
+    if let Some(Command::Explain) = opt.cmd {
+        return explain(&opt, &layout);
+    }
+
+    if let Some(Command::Export { .. }) = opt.cmd {
+        return export(&opt, &layout);
+    }
+
+    if let Some(Command::Fidelity { resolution }) = opt.cmd {
+        return fidelity(&opt, &layout, resolution);
+    }
+
+    let config = match get_config(&opt, &layout) {
+        Ok(config) => config,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let mut diagnostics = Diagnostics::new();
+    diagnostics.echo = opt.diagnostics == "verbose";
+    // every track gets counted, its messages are only kept when they are shown or written
+    diagnostics.track_details = opt.diagnostics == "verbose" || opt.diagnostics_json.is_some();
+
+    // task time elapsed
+    let start = Instant::now();
+
+    let mut cache = match opt.cache.as_ref().map(ParseCache::new).transpose() {
+        Ok(cache) => cache,
+        Err(e) => {
+            println!("Cache error: {}", e);
+            return;
+        }
+    };
+
+    let pool = match load(&opt, &layout, &config, cache.as_mut(), &mut diagnostics) {
+        Ok(pool) => pool,
+        Err(e) => {
+            println!("{}", e);
+            return;
+        }
+    };
+
+    let (resolution, offset, span) = get_row_spec(&opt);
+    let track_grids = grid(&opt, &pool, &layout, resolution, cache.as_mut(), &mut diagnostics);
+
+    let built = rows(&opt, &layout, &config, &pool, &track_grids, (offset, span), &mut diagnostics)
+        .and_then(|(rows, kept)| {
+            let (kept, split_ids) = split(&opt, &config, &pool, &rows, kept, &mut diagnostics);
+            augment(&opt, &config, rows, kept, split_ids, resolution)
+        });
+
+    match built {
+        Ok(dataset) => {
+            let labels = labels(&config, &layout, &pool, &track_grids, &dataset, offset);
+            write(&opt, &layout, &config, pool, &dataset, &labels, resolution);
         }
         Err(err) => {
-            println!("Shape error: {}", err);
+            println!("Processing error: {}", err);
+        }
+    }
+
+    if opt.diagnostics != "quiet" {
+        println!("{}", diagnostics.summary());
+    }
+
+    if let Some(json_path) = &opt.diagnostics_json {
+        match diagnostics.to_json().and_then(|json| Ok(fs::write(json_path, json)?)) {
+            Ok(_) => println!("Successfully generated diagnostics JSON for path: '{}'", json_path),
+            Err(e) => println!("Diagnostics write error: {}", e),
         }
     }
 
@@ -0,0 +1,360 @@
+use std::borrow::Cow;
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use ndarray::{Array1, ArrayView, ArrayViewD, Dimension};
+use ndarray_npy::{write_npy, NpzWriter};
+use parquet::basic::{LogicalType, Repetition, Type as PhysicalType};
+use parquet::data_type::{FloatType, Int32Type, Int64Type};
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::format::KeyValue;
+use parquet::schema::types::{Type, TypePtr};
+use serde_json::json;
+
+use midi_parse::error::MidiBeatError;
+use midi_parse::manifest::{ManifestArray, ManifestShard};
+use midi_parse::metadata::vocabulary_to_bytes;
+
+pub const FORMATS: [&str; 4] = ["npz", "npy", "safetensors", "parquet"];
+
+pub enum ShardArray<'a> {
+    F32(ArrayViewD<'a, f32>),
+    I64(ArrayViewD<'a, i64>),
+    U8(ArrayViewD<'a, u8>),
+}
+
+impl<'a> ShardArray<'a> {
+    // safetensors dtype name
+    fn dtype(&self) -> &'static str {
+        match self {
+            ShardArray::F32(_) => "F32",
+            ShardArray::I64(_) => "I64",
+            ShardArray::U8(_) => "U8",
+        }
+    }
+
+    fn bytes(&self) -> u64 {
+        let element_bytes = match self {
+            ShardArray::F32(_) => 4,
+            ShardArray::I64(_) => 8,
+            ShardArray::U8(_) => 1,
+        };
+        self.shape().iter().product::<usize>() as u64 * element_bytes
+    }
+
+    fn shape(&self) -> &[usize] {
+        match self {
+            ShardArray::F32(array) => array.shape(),
+            ShardArray::I64(array) => array.shape(),
+            ShardArray::U8(array) => array.shape(),
+        }
+    }
+}
+
+// everything written for a range of rows, the same content in every format:
+// arrays have one entry per row on their first axis, vocabularies are shared by all rows.
+// Arrays are views of the dataset arrays, nothing is copied before writing
+pub struct Shard<'a> {
+    pub rows: usize,
+    pub arrays: Vec<(String, ShardArray<'a>)>,
+    pub vocabularies: Vec<(String, &'a [String])>,
+}
+
+impl<'a> Shard<'a> {
+    pub fn new(rows: usize) -> Shard<'a> {
+        Shard {
+            rows,
+            arrays: vec![],
+            vocabularies: vec![],
+        }
+    }
+
+    pub fn add_f32<D: Dimension>(&mut self, name: &str, array: ArrayView<'a, f32, D>) {
+        self.arrays.push((name.to_owned(), ShardArray::F32(array.into_dyn())));
+    }
+
+    pub fn add_i64<D: Dimension>(&mut self, name: &str, array: ArrayView<'a, i64, D>) {
+        self.arrays.push((name.to_owned(), ShardArray::I64(array.into_dyn())));
+    }
+
+    pub fn add_u8<D: Dimension>(&mut self, name: &str, array: ArrayView<'a, u8, D>) {
+        self.arrays.push((name.to_owned(), ShardArray::U8(array.into_dyn())));
+    }
+
+    pub fn add_vocabulary(&mut self, name: &str, vocabulary: &'a [String]) {
+        self.vocabularies.push((name.to_owned(), vocabulary));
+    }
+
+    // uncompressed size of the arrays
+    pub fn bytes(&self) -> u64 {
+        self.arrays.iter().map(|(_, array)| array.bytes()).sum()
+    }
+
+    // manifest entry of the shard written to path, start_row is its first row in the dataset
+    pub fn to_manifest_shard(&self, path: &str, split: Option<&str>, start_row: usize) -> ManifestShard {
+        ManifestShard {
+            path: Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_else(|| path.to_owned()),
+            split: split.map(|split| split.to_owned()),
+            start_row,
+            rows: self.rows,
+            bytes: self.bytes(),
+            arrays: self
+                .arrays
+                .iter()
+                .map(|(name, array)| ManifestArray {
+                    name: name.clone(),
+                    dtype: array.dtype().to_owned(),
+                    shape: array.shape().to_vec(),
+                })
+                .collect(),
+            vocabularies: self.vocabularies.iter().map(|(name, _)| name.clone()).collect(),
+        }
+    }
+}
+
+fn npy_error(e: impl std::error::Error) -> MidiBeatError {
+    MidiBeatError::Serialization(e.to_string())
+}
+
+// compressed NPZ, vocabularies are newline separated UTF-8 bytes
+fn write_npz(shard: &Shard, path: &str) -> Result<(), MidiBeatError> {
+    let mut npz = NpzWriter::new_compressed(File::create(path)?);
+    for (name, array) in shard.arrays.iter() {
+        match array {
+            ShardArray::F32(array) => npz.add_array(name.as_str(), array),
+            ShardArray::I64(array) => npz.add_array(name.as_str(), array),
+            ShardArray::U8(array) => npz.add_array(name.as_str(), array),
+        }
+        .map_err(npy_error)?;
+    }
+    for (name, vocabulary) in shard.vocabularies.iter() {
+        npz.add_array(name.as_str(), &vocabulary_to_bytes(vocabulary))
+            .map_err(npy_error)?;
+    }
+    npz.finish().map_err(npy_error)?;
+    Ok(())
+}
+
+// one uncompressed .npy per array in a directory, they can be memory mapped
+fn write_npy_directory(shard: &Shard, path: &str) -> Result<(), MidiBeatError> {
+    fs::create_dir_all(path)?;
+    for (name, array) in shard.arrays.iter() {
+        let npy_path = format!("{}/{}.npy", path, name);
+        match array {
+            ShardArray::F32(array) => write_npy(&npy_path, array),
+            ShardArray::I64(array) => write_npy(&npy_path, array),
+            ShardArray::U8(array) => write_npy(&npy_path, array),
+        }
+        .map_err(npy_error)?;
+    }
+    for (name, vocabulary) in shard.vocabularies.iter() {
+        write_npy(format!("{}/{}.npy", path, name), &vocabulary_to_bytes(vocabulary))
+            .map_err(npy_error)?;
+    }
+    Ok(())
+}
+
+fn write_tensor(file: &mut impl Write, array: &ShardArray) -> std::io::Result<()> {
+    match array {
+        ShardArray::F32(array) => array.iter().try_for_each(|value| file.write_all(&value.to_le_bytes())),
+        ShardArray::I64(array) => array.iter().try_for_each(|value| file.write_all(&value.to_le_bytes())),
+        ShardArray::U8(array) => match array.as_slice() {
+            Some(bytes) => file.write_all(bytes),
+            None => array.iter().try_for_each(|value| file.write_all(&[*value])),
+        },
+    }
+}
+
+// safetensors: header length (u64 LE), JSON header, then the little endian data of each tensor,
+// vocabularies are U8 tensors like in NPZ, written after the arrays
+fn write_safetensors(shard: &Shard, path: &str) -> Result<(), MidiBeatError> {
+    let vocabularies: Vec<(&String, Array1<u8>)> = shard
+        .vocabularies
+        .iter()
+        .map(|(name, vocabulary)| (name, vocabulary_to_bytes(vocabulary)))
+        .collect();
+    let vocabularies: Vec<(&String, ShardArray)> = vocabularies
+        .iter()
+        .map(|(name, bytes)| (*name, ShardArray::U8(bytes.view().into_dyn())))
+        .collect();
+
+    let mut header = serde_json::Map::new();
+    let mut end: u64 = 0;
+    let mut add_tensor = |name: &String, array: &ShardArray| {
+        let start = end;
+        end += array.bytes();
+        header.insert(
+            name.clone(),
+            json!({"dtype": array.dtype(), "shape": array.shape(), "data_offsets": [start, end]}),
+        );
+    };
+    shard.arrays.iter().for_each(|(name, array)| add_tensor(name, array));
+    vocabularies.iter().for_each(|(name, array)| add_tensor(name, array));
+
+    // the header is padded with spaces so that the data is 8 bytes aligned
+    let mut header = serde_json::to_vec(&header).map_err(|e| MidiBeatError::Serialization(e.to_string()))?;
+    header.resize(header.len().div_ceil(8) * 8, b' ');
+
+    let mut file = BufWriter::new(File::create(path)?);
+    file.write_all(&(header.len() as u64).to_le_bytes())?;
+    file.write_all(&header)?;
+    for (_, array) in shard.arrays.iter() {
+        write_tensor(&mut file, array)?;
+    }
+    for (_, array) in vocabularies.iter() {
+        write_tensor(&mut file, array)?;
+    }
+    file.flush()?;
+    Ok(())
+}
+
+fn parquet_error(e: ParquetError) -> MidiBeatError {
+    MidiBeatError::Serialization(e.to_string())
+}
+
+// scalar column for 1D arrays, list column of the flattened row values for the others,
+// names are taken as they are, whatever their characters
+fn get_parquet_field(name: &str, array: &ShardArray) -> Result<TypePtr, ParquetError> {
+    let (physical, logical) = match array {
+        ShardArray::F32(_) => (PhysicalType::FLOAT, None),
+        ShardArray::I64(_) => (PhysicalType::INT64, None),
+        ShardArray::U8(_) => (
+            PhysicalType::INT32,
+            Some(LogicalType::Integer {
+                bit_width: 8,
+                is_signed: false,
+            }),
+        ),
+    };
+    let value = |name: &str| {
+        Type::primitive_type_builder(name, physical)
+            .with_repetition(Repetition::REQUIRED)
+            .with_logical_type(logical.clone())
+            .build()
+    };
+
+    if array.shape().len() == 1 {
+        return Ok(Arc::new(value(name)?));
+    }
+
+    let list = Type::group_type_builder("list")
+        .with_repetition(Repetition::REPEATED)
+        .with_fields(vec![Arc::new(value("element")?)])
+        .build()?;
+    Ok(Arc::new(
+        Type::group_type_builder(name)
+            .with_repetition(Repetition::REQUIRED)
+            .with_logical_type(Some(LogicalType::List))
+            .with_fields(vec![Arc::new(list)])
+            .build()?,
+    ))
+}
+
+fn contiguous<'a, A: Clone>(array: &'a ArrayViewD<A>) -> Cow<'a, [A]> {
+    match array.as_slice() {
+        Some(values) => Cow::Borrowed(values),
+        None => Cow::Owned(array.iter().cloned().collect()),
+    }
+}
+
+// Parquet, one row per dataset row: 1D arrays are scalar columns, others list columns of the
+// flattened row values, whose shapes and the vocabularies are in the key value metadata
+fn write_parquet(shard: &Shard, path: &str) -> Result<(), MidiBeatError> {
+    let fields: Vec<TypePtr> = shard
+        .arrays
+        .iter()
+        .map(|(name, array)| get_parquet_field(name, array))
+        .collect::<Result<Vec<TypePtr>, ParquetError>>()
+        .map_err(parquet_error)?;
+    let schema = Type::group_type_builder("shard")
+        .with_fields(fields)
+        .build()
+        .map_err(parquet_error)?;
+
+    let mut key_values: Vec<KeyValue> = shard
+        .arrays
+        .iter()
+        .filter(|(_, array)| array.shape().len() > 1)
+        .map(|(name, array)| {
+            let shape: Vec<String> = array.shape()[1..].iter().map(|length| length.to_string()).collect();
+            KeyValue::new(format!("{}_shape", name), shape.join(","))
+        })
+        .collect();
+    key_values.extend(
+        shard
+            .vocabularies
+            .iter()
+            .map(|(name, vocabulary)| KeyValue::new(name.clone(), vocabulary.join("\n"))),
+    );
+    let properties = WriterProperties::builder().set_key_value_metadata(Some(key_values)).build();
+
+    let mut writer =
+        SerializedFileWriter::new(File::create(path)?, Arc::new(schema), Arc::new(properties)).map_err(parquet_error)?;
+    let mut row_group = writer.next_row_group().map_err(parquet_error)?;
+
+    for (_, array) in shard.arrays.iter() {
+        let mut column = match row_group.next_column().map_err(parquet_error)? {
+            Some(column) => column,
+            None => break,
+        };
+
+        // lists start a new record on their first value, every value is defined
+        let row_length = array.shape()[1..].iter().product::<usize>();
+        let levels = if array.shape().len() > 1 {
+            let definition = vec![1; shard.rows * row_length];
+            let repetition = (0..shard.rows * row_length)
+                .map(|index| if index % row_length == 0 { 0 } else { 1 })
+                .collect();
+            Some((definition, repetition))
+        } else {
+            None
+        };
+        let definition = levels.as_ref().map(|(definition, _): &(Vec<i16>, Vec<i16>)| definition.as_slice());
+        let repetition = levels.as_ref().map(|(_, repetition)| repetition.as_slice());
+
+        // row slices of the dataset arrays are contiguous, they are written as they are
+        match array {
+            ShardArray::F32(array) => column
+                .typed::<FloatType>()
+                .write_batch(&contiguous(array), definition, repetition),
+            ShardArray::I64(array) => column
+                .typed::<Int64Type>()
+                .write_batch(&contiguous(array), definition, repetition),
+            ShardArray::U8(array) => {
+                let values: Vec<i32> = array.iter().map(|&value| value as i32).collect();
+                column.typed::<Int32Type>().write_batch(&values, definition, repetition)
+            }
+        }
+        .map_err(parquet_error)?;
+        column.close().map_err(parquet_error)?;
+    }
+
+    row_group.close().map_err(parquet_error)?;
+    writer.close().map_err(parquet_error)?;
+    Ok(())
+}
+
+// writes the shard to path_prefix plus the format extension, returns the written path
+pub fn write_shard(shard: &Shard, format: &str, path_prefix: &str) -> Result<String, MidiBeatError> {
+    let path = match format {
+        "npy" => path_prefix.to_owned(),
+        _ => format!("{}.{}", path_prefix, format),
+    };
+
+    match format {
+        "npz" => write_npz(shard, &path)?,
+        "npy" => write_npy_directory(shard, &path)?,
+        "safetensors" => write_safetensors(shard, &path)?,
+        "parquet" => write_parquet(shard, &path)?,
+        _ => return Err(MidiBeatError::Config(format!("unknown output format {}", format))),
+    }
+
+    Ok(path)
+}
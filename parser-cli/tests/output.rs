@@ -0,0 +1,144 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use ndarray::{Array, Ix4};
+use ndarray_npy::{read_npy, NpzReader};
+
+use midi_parse::datatypes::Bar;
+use midi_parse::export::{write_midi, ExportSettings};
+use midi_parse::map::{get_layout, DEFAULT_LAYOUT, NUMBER_OF_TRACKS, RESOLUTION};
+use midi_parse::reader::ManifestReader;
+
+// hh every 4 steps, a kick moving a step every bar so that no two bars repeat,
+// velocities are MIDI velocities so that they survive the round trip
+fn bars() -> Vec<Bar> {
+    (0..4)
+        .map(|bar_index| {
+            let mut bar = Bar::new(RESOLUTION, NUMBER_OF_TRACKS);
+            for step in (0..RESOLUTION).step_by(4) {
+                bar.set(step, 5, 64. / 127., 0.);
+            }
+            bar.set(2 * bar_index + 1, 0, 100. / 127., 0.);
+            bar.set(8, 1, 90. / 127., 0.);
+            bar
+        })
+        .collect()
+}
+
+// writes the bars to a MIDI file in a fresh directory and builds a dataset of it with args,
+// returns the directory, the dataset is {directory}/out
+fn build(name: &str, args: &[&str]) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("parser-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+
+    let layout = get_layout(DEFAULT_LAYOUT).unwrap();
+    write_midi(directory.join("groove.mid"), &bars(), &ExportSettings::new(&layout)).unwrap();
+
+    let output = Command::new(env!("CARGO_BIN_EXE_parser-cli"))
+        .arg("-i")
+        .arg(directory.join("*.mid"))
+        .arg("-o")
+        .arg(directory.join("out"))
+        .args(args)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    directory
+}
+
+#[test]
+fn safetensors_data_starts_8_bytes_aligned() {
+    let directory = build("safetensors", &["--format", "safetensors"]);
+    let bytes = fs::read(directory.join("out_0.safetensors")).unwrap();
+
+    let mut length = [0u8; 8];
+    length.copy_from_slice(&bytes[..8]);
+    let header_length = u64::from_le_bytes(length) as usize;
+    assert_eq!(header_length % 8, 0);
+
+    // the padding is spaces after the JSON
+    let header = &bytes[8..8 + header_length];
+    let json_end = header.iter().rposition(|byte| *byte == b'}').unwrap() + 1;
+    assert!(header[json_end..].iter().all(|byte| *byte == b' '));
+    let header: serde_json::Value = serde_json::from_slice(header).unwrap();
+
+    // tensors follow each other up to the end of the file
+    let x = &header["x"];
+    assert_eq!(x["dtype"], "F32");
+    assert_eq!(x["shape"], serde_json::json!([4, RESOLUTION, NUMBER_OF_TRACKS, 2]));
+    assert_eq!(x["data_offsets"][0], 0);
+    let data_end = header
+        .as_object()
+        .unwrap()
+        .values()
+        .map(|tensor| tensor["data_offsets"][1].as_u64().unwrap())
+        .max()
+        .unwrap();
+    assert_eq!(bytes.len() - 8 - header_length, data_end as usize);
+}
+
+#[test]
+fn npz_and_npy_hold_the_same_grids() {
+    let directory = build("npz", &["--format", "npz"]);
+    let mut npz = NpzReader::new(fs::File::open(directory.join("out_0.npz")).unwrap()).unwrap();
+    let from_npz: Array<f32, Ix4> = npz.by_name("x").unwrap();
+
+    let directory = build("npy", &["--format", "npy"]);
+    let from_npy: Array<f32, Ix4> = read_npy(directory.join("out_0").join("x.npy")).unwrap();
+
+    assert_eq!(from_npz.shape(), &[4, RESOLUTION, NUMBER_OF_TRACKS, 2]);
+    assert_eq!(from_npz, from_npy);
+    for (row, bar) in from_npz.outer_iter().zip(bars().iter()) {
+        assert_eq!(Bar::from_view(row).unwrap().number_of_hits(), bar.number_of_hits());
+    }
+}
+
+#[test]
+fn parquet_shards_are_parquet_files() {
+    let directory = build("parquet", &["--format", "parquet"]);
+    let bytes = fs::read(directory.join("out_0.parquet")).unwrap();
+
+    assert_eq!(&bytes[..4], b"PAR1");
+    assert_eq!(&bytes[bytes.len() - 4..], b"PAR1");
+}
+
+#[test]
+fn written_rows_are_read_back_through_the_manifest() {
+    for format in ["npz", "npy", "safetensors"].iter() {
+        let directory = build(&format!("read-{}", format), &["--format", format]);
+        let reader = ManifestReader::open(directory.join("out_manifest.json")).unwrap();
+        assert_eq!(reader.manifest.features, vec!["x.velocity", "x.offset"]);
+
+        let rows: Vec<Vec<Bar>> = reader.rows().collect::<Result<_, _>>().unwrap();
+        assert_eq!(rows.len(), 4, "{}", format);
+        for (row, bar) in rows.iter().zip(bars().iter()) {
+            assert_eq!(row.len(), 1);
+            for step in 0..RESOLUTION {
+                for lane in 0..NUMBER_OF_TRACKS {
+                    assert_eq!(row[0].hit(step, lane), bar.hit(step, lane), "{} step {} lane {}", format, step, lane);
+                    assert!((row[0].velocity(step, lane) - bar.velocity(step, lane)).abs() < 1e-5);
+                }
+            }
+        }
+    }
+}
+
+#[test]
+fn manifest_features_follow_the_written_arrays() {
+    let directory = build("pairs", &["--format", "npz", "--pairs-context", "1"]);
+    let reader = ManifestReader::open(directory.join("out_manifest.json")).unwrap();
+    assert_eq!(
+        reader.manifest.features,
+        vec!["context.velocity", "context.offset", "target.velocity", "target.offset"]
+    );
+
+    let directory = build("gmd", &["--format", "npz", "--gmd", "--layout", "gmd9"]);
+    let reader = ManifestReader::open(directory.join("out_manifest.json")).unwrap();
+    assert_eq!(reader.manifest.features, vec!["hits", "velocities", "offsets"]);
+
+    // parquet shards have no reader
+    let directory = build("parquet-manifest", &["--format", "parquet"]);
+    assert!(ManifestReader::open(directory.join("out_manifest.json")).is_err());
+}